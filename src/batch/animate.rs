@@ -0,0 +1,162 @@
+use std::{
+  fs,
+  path::PathBuf,
+  sync::mpsc::{Sender, Receiver}
+};
+use clap::{App, ArgMatches};
+use term_painter::{ToStyle, Color};
+use crate::opencl::{Action, ActionResult, SaveOptions, AnimatedFormat, AnimatedWriter};
+use crate::scene::{Scene, Keyframe, sample_keyframes};
+use super::{optional_value, optional_values, check_overwrite, get_state, set_params, new_image, render, save_image, get_image};
+
+/// `animate --delay`, ms
+pub const DEFAULT_DELAY: u32 = 40;
+
+pub struct Animation {
+  pub frames: u32,
+  pub iterations: u32,
+  pub dimensions: Vec<u32>,
+  pub image_size: (u32, u32),
+  /// frame n is rendered with `seed + n`, unless `fixed_seed`
+  pub seed: u64,
+  /// same seed on every frame, less flicker between similar frames
+  pub fixed_seed: bool,
+  /// frame directory
+  pub out: Option<PathBuf>,
+  pub gif: Option<PathBuf>,
+  pub apng: Option<PathBuf>,
+  /// per frame of the gif and apng, ms
+  pub delay: u32,
+  /// overwrite existing frames and animations
+  pub force: bool
+}
+
+pub fn animate_app() -> App<'static, 'static> {
+  clap_app!(animate =>
+    (about: "render the keyframes of the scene into an image sequence")
+    (@arg frames: -f --frames +takes_value +required "frame count")
+    (@arg iter: -i --("iter-per-frame") +takes_value "iteration count per frame")
+    (@arg dimensions: -d --dimensions +takes_value +multiple "worker dimensions")
+    (@arg size: --size +takes_value number_of_values(2) "image size, width height")
+    (@arg seed: -s --seed +takes_value "seed of the first frame")
+    (@arg fixed_seed: --("fixed-seed") "same seed on every frame")
+    (@arg out: -o --out +takes_value "output directory of the frames")
+    (@arg gif: --gif +takes_value "animated gif, 256 colors per frame")
+    (@arg apng: --apng +takes_value "animated png")
+    (@arg delay: --delay +takes_value "frame delay of the gif and apng, ms")
+    (@arg force: --force "overwrite existing frames and animations")
+  )
+}
+
+impl Animation {
+  /// `defaults` provides the omitted options, as for `new` and `render`
+  pub fn from_matches(command: &ArgMatches, defaults: &Scene) -> Result<Animation, String> {
+    let frames = optional_value::<u32>(command, "frames")?.unwrap();
+    if frames == 0 {
+      return Err("frame count must be positive".into());
+    }
+    if !["out", "gif", "apng"].iter().any(|&name| command.is_present(name)) {
+      return Err("no output, give --out, --gif or --apng".into());
+    }
+    let image_size = match optional_values::<u32>(command, "size")? {
+      Some(size) => (size[0], size[1]),
+      None => (defaults.image.width, defaults.image.height)
+    };
+    Ok(Animation {
+      frames,
+      iterations: optional_value(command, "iter")?.unwrap_or(defaults.render.iter),
+      dimensions: optional_values(command, "dimensions")?.unwrap_or_else(|| defaults.render.dimensions.clone()),
+      image_size,
+      seed: optional_value(command, "seed")?.or(defaults.image.seed).unwrap_or_else(rand::random),
+      fixed_seed: command.is_present("fixed_seed"),
+      out: command.value_of("out").map(PathBuf::from),
+      gif: command.value_of("gif").map(PathBuf::from),
+      apng: command.value_of("apng").map(PathBuf::from),
+      delay: optional_value(command, "delay")?.unwrap_or(DEFAULT_DELAY),
+      force: command.is_present("force")
+    })
+  }
+
+  /// Keyframe time of a frame, the frames span the first to the last keyframe
+  pub fn time(&self, keyframes: &[Keyframe], frame: u32) -> f32 {
+    let (start, end) = match (keyframes.first(), keyframes.last()) {
+      (Some(first), Some(last)) => (first.time, last.time),
+      _ => return 0.0
+    };
+    if self.frames < 2 {
+      return start;
+    }
+    start + (end - start) * frame as f32 / (self.frames - 1) as f32
+  }
+
+  pub fn frame_seed(&self, frame: u32) -> u64 {
+    if self.fixed_seed { self.seed } else { self.seed.wrapping_add(frame as u64) }
+  }
+
+  /// Renders every frame into `out` and the animations, then restores the parameters of the current image
+  pub fn run(
+    &self,
+    tx1: &Sender<Action>,
+    rx2: &Receiver<ActionResult>,
+    keyframes: &[Keyframe],
+    options: &SaveOptions
+  ) -> Result<(), String> {
+    if keyframes.is_empty() {
+      return Err("no keyframes, add some with `keyframe add` or a scene file".into());
+    }
+    let frame_paths: Vec<PathBuf> = match &self.out {
+      Some(out) => (0..self.frames).map(|frame| out.join(format!("frame_{:05}.png", frame))).collect(),
+      None => vec![]
+    };
+    check_overwrite(frame_paths.iter().map(PathBuf::as_path), self.force)?;
+    if let Some(out) = &self.out {
+      fs::create_dir_all(out).map_err(|e| format!("\"{}\": {}", out.display(), e))?;
+    }
+    let options = &SaveOptions { force: self.force, ..options.clone() };
+    let state = get_state(tx1, rx2)?;
+    if state.rendering {
+      return Err("a render is in progress, cancel it first".into());
+    }
+    let mut writers = vec![];
+    for (path, format) in vec![(&self.gif, AnimatedFormat::Gif), (&self.apng, AnimatedFormat::Apng)] {
+      if let Some(path) = path {
+        let writer = AnimatedWriter::new(path, format, self.image_size, self.delay, self.force)
+          .map_err(|e| format!("\"{}\": {}", path.display(), e))?;
+        writers.push(writer);
+      }
+    }
+
+    for frame in 0..self.frames {
+      let time = self.time(keyframes, frame);
+      let params = sample_keyframes(keyframes, time).unwrap();
+      println!("{} [{}/{}] time {}", Color::Green.paint("animate:"), frame + 1, self.frames, time);
+
+      let mut result = set_params(tx1, rx2, &params)
+        .and_then(|_| new_image(tx1, rx2, self.image_size, self.frame_seed(frame)))
+        .and_then(|_| render(tx1, rx2, self.iterations, &self.dimensions));
+      if let (Ok(()), Some(path)) = (&result, frame_paths.get(frame as usize)) {
+        result = save_image(tx1, rx2, options, path);
+      }
+      if let (Ok(()), false) = (&result, writers.is_empty()) {
+        result = get_image(tx1, rx2, options).and_then(|(_, pixels)| {
+          writers.iter_mut().map(|writer| writer.add_frame(&pixels)).collect()
+        });
+      }
+      if let Err(e) = result {
+        set_params(tx1, rx2, &state.params).ok();
+        return Err(format!("frame {}: {}", frame, e));
+      }
+    }
+
+    set_params(tx1, rx2, &state.params)?;
+    if let Some(out) = &self.out {
+      println!("{} {} frames saved to \"{}\"", Color::Green.paint("animate:"), self.frames, out.display());
+    }
+    for writer in writers {
+      let path = writer.path().to_path_buf();
+      writer.finish().map_err(|e| format!("\"{}\": {}", path.display(), e))?;
+      println!("{} animation saved to \"{}\"", Color::Green.paint("animate:"), path.display());
+    }
+    Ok(())
+  }
+}
\ No newline at end of file
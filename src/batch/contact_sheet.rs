@@ -0,0 +1,130 @@
+use std::path::Path;
+use image::{RgbaImage, Rgba, imageops};
+
+/// 5x7 glyphs, one byte per row, bit 4 is the leftmost column
+const GLYPHS: &[(char, [u8; 7])] = &[
+  ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+  ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+  ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+  ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+  ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+  ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+  ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+  ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+  ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+  ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+  ('A', [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+  ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+  ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+  ('D', [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C]),
+  ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+  ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+  ('G', [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F]),
+  ('H', [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+  ('I', [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+  ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C]),
+  ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+  ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F]),
+  ('M', [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11]),
+  ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+  ('O', [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+  ('P', [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10]),
+  ('Q', [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D]),
+  ('R', [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11]),
+  ('S', [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E]),
+  ('T', [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+  ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+  ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04]),
+  ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A]),
+  ('X', [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11]),
+  ('Y', [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04]),
+  ('Z', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F]),
+  ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C]),
+  ('-', [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00]),
+  ('+', [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00]),
+  ('=', [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00]),
+  ('_', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F]),
+  (':', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00]),
+  (' ', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+  ('?', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04])
+];
+
+const GLYPH_WIDTH: u32 = 6; // including spacing
+const LINE_HEIGHT: u32 = 9;
+const PADDING: u32 = 4;
+
+fn glyph(c: char) -> &'static [u8; 7] {
+  let c = c.to_ascii_uppercase();
+  GLYPHS.iter()
+    .find(|(x, _)| *x == c)
+    .or_else(|| GLYPHS.iter().find(|(x, _)| *x == '?'))
+    .map(|(_, rows)| rows)
+    .unwrap()
+}
+
+/// Draws `text` in uppercase, clipped to the image
+pub fn draw_text(image: &mut RgbaImage, x: u32, y: u32, text: &str, color: Rgba<u8>) {
+  for (i, c) in text.chars().enumerate() {
+    let rows = glyph(c);
+    for (row, bits) in rows.iter().enumerate() {
+      for column in 0..5 {
+        if bits & (0x10 >> column) == 0 {
+          continue;
+        }
+        let (px, py) = (x + i as u32 * GLYPH_WIDTH + column, y + row as u32);
+        if px < image.width() && py < image.height() {
+          image.put_pixel(px, py, color);
+        }
+      }
+    }
+  }
+}
+
+/// Grid of labelled thumbnails
+pub struct ContactSheet {
+  columns: usize,
+  /// thumbnail size, px
+  cell: (u32, u32),
+  cells: Vec<(Option<RgbaImage>, Vec<String>)>
+}
+
+impl ContactSheet {
+  pub fn new(columns: usize, cell_width: u32, image_size: (u32, u32)) -> ContactSheet {
+    let cell_height = (cell_width as u64 * image_size.1 as u64 / image_size.0.max(1) as u64).max(1) as u32;
+    ContactSheet { columns: columns.max(1), cell: (cell_width, cell_height), cells: vec![] }
+  }
+
+  /// Thumbnail of the image at `path`, a "failed" cell if it is missing or unreadable
+  pub fn add(&mut self, path: Option<&Path>, labels: &[String]) {
+    let thumbnail = path
+      .and_then(|path| image::open(path).ok())
+      .map(|image| imageops::thumbnail(&image.to_rgba(), self.cell.0, self.cell.1));
+    self.cells.push((thumbnail, labels.to_vec()));
+  }
+
+  pub fn save(&self, path: &Path) -> Result<(), String> {
+    let lines = self.cells.iter().map(|(_, labels)| labels.len()).max().unwrap_or(0) as u32;
+    let cell_width = self.cell.0 + PADDING * 2;
+    let cell_height = self.cell.1 + PADDING * 2 + lines * LINE_HEIGHT;
+    let rows = (self.cells.len() + self.columns - 1) / self.columns;
+    let mut sheet = RgbaImage::from_pixel(
+      cell_width * self.columns as u32,
+      cell_height * rows as u32,
+      Rgba([0x20, 0x20, 0x20, 0xFF])
+    );
+
+    for (i, (thumbnail, labels)) in self.cells.iter().enumerate() {
+      let x = (i % self.columns) as u32 * cell_width + PADDING;
+      let y = (i / self.columns) as u32 * cell_height + PADDING;
+      match thumbnail {
+        Some(thumbnail) => imageops::overlay(&mut sheet, thumbnail, x, y),
+        None => draw_text(&mut sheet, x, y, "failed", Rgba([0xFF, 0x40, 0x40, 0xFF]))
+      }
+      for (line, label) in labels.iter().enumerate() {
+        let y = y + self.cell.1 + 2 + line as u32 * LINE_HEIGHT;
+        draw_text(&mut sheet, x, y, label, Rgba([0xE0, 0xE0, 0xE0, 0xFF]));
+      }
+    }
+    sheet.save(path).map_err(|e| e.to_string())
+  }
+}
\ No newline at end of file
@@ -0,0 +1,121 @@
+mod sweep;
+mod contact_sheet;
+mod animate;
+
+use std::{
+  path::Path,
+  str::FromStr,
+  sync::mpsc::{channel, Sender, Receiver}
+};
+use clap::ArgMatches;
+use crate::opencl::{Action, ActionResult, ThreadState, RenderParams, RenderLimits, SaveOptions, StopReason};
+pub use sweep::*;
+pub use contact_sheet::*;
+pub use animate::*;
+
+/* Batch renders drive the opencl thread with the same actions as the repl,
+ * one image at a time: SetParams, New, Render (waiting for its callback), SaveImage
+ */
+
+fn get_state(tx1: &Sender<Action>, rx2: &Receiver<ActionResult>) -> Result<ThreadState, String> {
+  tx1.send(Action::GetState).unwrap();
+  match rx2.recv().unwrap() {
+    ActionResult::State(state) => Ok(state),
+    _ => Err("unable to get render state".into())
+  }
+}
+
+pub fn current_params(tx1: &Sender<Action>, rx2: &Receiver<ActionResult>) -> Result<RenderParams, String> {
+  get_state(tx1, rx2).map(|state| state.params)
+}
+
+pub fn set_params(tx1: &Sender<Action>, rx2: &Receiver<ActionResult>, params: &RenderParams) -> Result<(), String> {
+  params.validate()?;
+  tx1.send(Action::SetParams(params.clone())).unwrap();
+  match rx2.recv().unwrap() {
+    ActionResult::Ok => Ok(()),
+    _ => Err("unable to apply parameters".into())
+  }
+}
+
+fn new_image(tx1: &Sender<Action>, rx2: &Receiver<ActionResult>, size: (u32, u32), seed: u64) -> Result<(), String> {
+  tx1.send(Action::New(size.0, size.1, Some(seed))).unwrap();
+  match rx2.recv().unwrap() {
+    ActionResult::Ok => Ok(()),
+    _ => Err("unable to create image".into())
+  }
+}
+
+/// Blocks until the render is finished, an error unless every iteration was rendered
+fn render(tx1: &Sender<Action>, rx2: &Receiver<ActionResult>, iterations: u32, dimensions: &[u32]) -> Result<(), String> {
+  let (done_tx, done_rx) = channel::<StopReason>();
+  let callback: Box<dyn FnMut(StopReason) + Send> = Box::new(move |stop| { done_tx.send(stop).ok(); });
+  tx1.send(Action::Render(iterations, dimensions.to_vec(), None, None, None, RenderLimits::default(), Some(callback))).unwrap();
+  if !rx2.recv().unwrap().is_ok() {
+    return Err("unable to render".into());
+  }
+  match done_rx.recv() {
+    Ok(StopReason::Iterations) => Ok(()),
+    Ok(stop) => Err(format!("render stopped: {}", stop)),
+    Err(_) => Err("render was dropped".into())
+  }
+}
+
+/// The format follows the extension of `path`, existing files are overwritten with `options.force` only
+fn save_image(tx1: &Sender<Action>, rx2: &Receiver<ActionResult>, options: &SaveOptions, path: &Path) -> Result<(), String> {
+  tx1.send(Action::SaveImage(SaveOptions {
+    output: Some(path.into()),
+    format: None,
+    ..options.clone()
+  })).unwrap();
+  match rx2.recv().unwrap() {
+    ActionResult::Ok => Ok(()),
+    _ => Err(format!("unable to save \"{}\"", path.display()))
+  }
+}
+
+/// Checked before rendering, so that a batch does not stop halfway
+fn check_overwrite<'a, I: IntoIterator<Item = &'a Path>>(paths: I, force: bool) -> Result<(), String> {
+  if force {
+    return Ok(());
+  }
+  match paths.into_iter().find(|path| path.exists()) {
+    Some(path) => Err(format!("\"{}\" already exists, use --force to overwrite", path.display())),
+    None => Ok(())
+  }
+}
+
+/// Current image as RGBA8, for animations
+fn get_image(tx1: &Sender<Action>, rx2: &Receiver<ActionResult>, options: &SaveOptions) -> Result<((u32, u32), Vec<u8>), String> {
+  tx1.send(Action::GetImage(options.clone())).unwrap();
+  match rx2.recv().unwrap() {
+    ActionResult::Image(size, pixels) => Ok((size, pixels)),
+    _ => Err("unable to read image".into())
+  }
+}
+
+/// None if the option is absent, an error if it is given but does not parse
+pub fn optional_value<T: FromStr>(command: &ArgMatches, name: &str) -> Result<Option<T>, String> {
+  match command.value_of(name) {
+    Some(value) => value.parse().map(Some).map_err(|_| format!("invalid {} \"{}\"", name, value)),
+    None => Ok(None)
+  }
+}
+
+/// As `optional_value`, for options taking several values
+pub fn optional_values<T: FromStr>(command: &ArgMatches, name: &str) -> Result<Option<Vec<T>>, String> {
+  match command.values_of(name) {
+    Some(values) => values
+      .map(|value| value.parse().map_err(|_| format!("invalid {} \"{}\"", name, value)))
+      .collect::<Result<Vec<T>, String>>()
+      .map(Some),
+    None => Ok(None)
+  }
+}
+
+/// "0.5" rather than "0.500000", "-1" rather than "-1.0000"
+pub fn format_value(value: f32) -> String {
+  let s = format!("{:.4}", value);
+  let s = s.trim_end_matches('0').trim_end_matches('.');
+  if s == "-0" { "0".into() } else { s.into() }
+}
\ No newline at end of file
@@ -0,0 +1,178 @@
+use std::{
+  fs,
+  str::FromStr,
+  path::PathBuf,
+  sync::mpsc::{Sender, Receiver}
+};
+use clap::{App, ArgMatches};
+use term_painter::{ToStyle, Color};
+use crate::opencl::{Action, ActionResult, RenderParams, SaveOptions};
+use crate::scene::Scene;
+use super::{optional_value, optional_values, check_overwrite, get_state, set_params, new_image, render, save_image, format_value, ContactSheet};
+
+/// `sweep --thumbnail`, px
+pub const DEFAULT_THUMBNAIL: u32 = 192;
+
+/// `name=start..end:steps`, steps values including both ends
+#[derive(Clone, PartialEq, Debug)]
+pub struct SweepAxis {
+  pub name: String,
+  pub values: Vec<f32>
+}
+
+impl FromStr for SweepAxis {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid = || format!("invalid sweep \"{}\", expected name=start..end:steps", s);
+    let mut parts = s.splitn(2, '=');
+    let name = parts.next().ok_or_else(invalid)?.trim();
+    let range = parts.next().ok_or_else(invalid)?;
+    RenderParams::default().get(name)?;
+
+    let mut parts = range.splitn(2, ':');
+    let bounds = parts.next().ok_or_else(invalid)?;
+    let steps: usize = parts.next().ok_or_else(invalid)?.parse().map_err(|_| invalid())?;
+    let mut bounds = bounds.splitn(2, "..");
+    let start: f32 = bounds.next().ok_or_else(invalid)?.parse().map_err(|_| invalid())?;
+    let end: f32 = bounds.next().ok_or_else(invalid)?.parse().map_err(|_| invalid())?;
+    if steps == 0 {
+      return Err(invalid());
+    }
+
+    let values = (0..steps)
+      .map(|i| if steps == 1 { start } else { start + (end - start) * i as f32 / (steps - 1) as f32 })
+      .collect();
+    Ok(SweepAxis { name: name.into(), values })
+  }
+}
+
+pub struct Sweep {
+  /// the last axis varies fastest, it becomes the columns of the contact sheet
+  pub axes: Vec<SweepAxis>,
+  pub iterations: u32,
+  pub dimensions: Vec<u32>,
+  pub image_size: (u32, u32),
+  /// shared by every image, so that only the parameters differ
+  pub seed: u64,
+  pub out: PathBuf,
+  /// contact sheet cell width, px
+  pub thumbnail: u32,
+  /// overwrite existing images
+  pub force: bool
+}
+
+pub fn sweep_app() -> App<'static, 'static> {
+  clap_app!(sweep =>
+    (about: "render every combination of parameter values, and a contact sheet")
+    (@arg param: -p --param +takes_value +multiple +required number_of_values(1) +allow_hyphen_values
+      "name=start..end:steps, e.g. a=0.5..2.0:16")
+    (@arg iter: -i --iter +takes_value "iteration count per image")
+    (@arg dimensions: -d --dimensions +takes_value +multiple "worker dimensions")
+    (@arg size: --size +takes_value number_of_values(2) "image size, width height")
+    (@arg seed: -s --seed +takes_value "seed of every image")
+    (@arg out: -o --out +takes_value +required "output directory")
+    (@arg thumbnail: --thumbnail +takes_value "contact sheet cell width, px")
+    (@arg force: --force "overwrite existing images")
+  )
+}
+
+impl Sweep {
+  /// `defaults` provides the omitted options, as for `new` and `render`
+  pub fn from_matches(command: &ArgMatches, defaults: &Scene) -> Result<Sweep, String> {
+    let axes = command.values_of("param").unwrap()
+      .map(|x| x.parse())
+      .collect::<Result<Vec<SweepAxis>, _>>()?;
+    let image_size = match optional_values::<u32>(command, "size")? {
+      Some(size) => (size[0], size[1]),
+      None => (defaults.image.width, defaults.image.height)
+    };
+    Ok(Sweep {
+      axes,
+      iterations: optional_value(command, "iter")?.unwrap_or(defaults.render.iter),
+      dimensions: optional_values(command, "dimensions")?.unwrap_or_else(|| defaults.render.dimensions.clone()),
+      image_size,
+      seed: optional_value(command, "seed")?.or(defaults.image.seed).unwrap_or_else(rand::random),
+      out: command.value_of("out").unwrap().into(),
+      thumbnail: optional_value(command, "thumbnail")?.unwrap_or(DEFAULT_THUMBNAIL),
+      force: command.is_present("force")
+    })
+  }
+
+  /// Every combination of axis values, row-major
+  pub fn combinations(&self) -> Vec<Vec<f32>> {
+    self.axes.iter().fold(vec![vec![]], |combinations, axis| {
+      combinations.iter()
+        .flat_map(|prefix| axis.values.iter().map(move |&value| {
+          let mut combination = prefix.clone();
+          combination.push(value);
+          combination
+        }))
+        .collect()
+    })
+  }
+
+  fn labels(&self, values: &[f32]) -> Vec<String> {
+    self.axes.iter()
+      .zip(values)
+      .map(|(axis, &value)| format!("{}={}", axis.name, format_value(value)))
+      .collect()
+  }
+
+  fn image_path(&self, labels: &[String]) -> PathBuf {
+    self.out.join(format!("{}.png", labels.join("_")))
+  }
+
+  /// Renders into `out`, then restores the parameters of the current image.
+  /// Stops at the first render that does not complete, e.g. on ctrl-c
+  pub fn run(
+    &self,
+    tx1: &Sender<Action>,
+    rx2: &Receiver<ActionResult>,
+    options: &SaveOptions
+  ) -> Result<(), String> {
+    let combinations = self.combinations();
+    let sheet_path = self.out.join("contact_sheet.png");
+    let paths = combinations.iter().map(|values| self.image_path(&self.labels(values))).collect::<Vec<_>>();
+    check_overwrite(paths.iter().chain(Some(&sheet_path)).map(PathBuf::as_path), self.force)?;
+    fs::create_dir_all(&self.out).map_err(|e| format!("\"{}\": {}", self.out.display(), e))?;
+    let state = get_state(tx1, rx2)?;
+    if state.rendering {
+      return Err("a render is in progress, cancel it first".into());
+    }
+    let base = state.params;
+    let options = SaveOptions { force: self.force, ..options.clone() };
+    let columns = self.axes.last().map_or(1, |axis| axis.values.len());
+    let mut sheet = ContactSheet::new(columns, self.thumbnail, self.image_size);
+
+    for (i, (values, path)) in combinations.iter().zip(&paths).enumerate() {
+      let mut params = base.clone();
+      for (axis, &value) in self.axes.iter().zip(values) {
+        params.set(&axis.name, value)?;
+      }
+      let labels = self.labels(values);
+      println!("{} [{}/{}] {}", Color::Green.paint("sweep:"), i + 1, combinations.len(), labels.join(" "));
+
+      let rendered = set_params(tx1, rx2, &params)
+        .and_then(|_| new_image(tx1, rx2, self.image_size, self.seed))
+        .and_then(|_| render(tx1, rx2, self.iterations, &self.dimensions));
+      if let Err(e) = rendered {
+        set_params(tx1, rx2, &base).ok();
+        return Err(format!("{}: {}", labels.join(" "), e));
+      }
+      let result = save_image(tx1, rx2, &options, path);
+      match result {
+        Ok(()) => sheet.add(Some(path), &labels),
+        Err(e) => {
+          println!("{} {}: {}", Color::BrightRed.paint("sweep::err:"), labels.join(" "), e);
+          sheet.add(None, &labels)
+        }
+      }
+    }
+
+    set_params(tx1, rx2, &base)?;
+    sheet.save(&sheet_path)?;
+    println!("{} contact sheet saved to \"{}\"", Color::Green.paint("sweep:"), sheet_path.display());
+    Ok(())
+  }
+}
\ No newline at end of file
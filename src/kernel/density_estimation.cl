@@ -0,0 +1,48 @@
+/*
+ * Density estimation, as done by fractal flame renderers:
+ * gaussian blur whose radius shrinks as the local sample count grows,
+ * smoothing sparse regions while keeping dense ones sharp.
+ * de = (radius_min, radius_max, curve, -)
+ */
+__constant int DE_RADIUS_LIMIT = 32;
+
+float density_estimate(
+    __global uint * accumulator,
+    int2 const pos,
+    uint2 const image_size,
+    float4 const de
+  )
+{
+  int2 size = convert_int2(image_size);
+  float density = (float)accumulator[pos.y * size.x + pos.x];
+  float radius = max(de.x, de.y / pow(density + 1.0f, de.z));
+  int support = min((int)ceil(radius), DE_RADIUS_LIMIT);
+
+  if (support < 1)
+    return density;
+
+  // sigma = radius / 2
+  float radius_sq = radius * radius;
+  float two_sigma_sq = radius_sq * 0.5f;
+  float sum = 0.0f;
+  float weight = 0.0f;
+
+  for (int dy = -support; dy <= support; dy++){
+    for (int dx = -support; dx <= support; dx++){
+      int2 p = pos + (int2)(dx, dy);
+      float distance_sq = (float)(dx * dx + dy * dy);
+      if (
+        (p.x < 0) || (p.y < 0) ||
+        (p.x >= size.x) || (p.y >= size.y) ||
+        (distance_sq > radius_sq)
+      )
+        continue;
+
+      float w = exp(-distance_sq / two_sigma_sq);
+      sum += w * (float)accumulator[p.y * size.x + p.x];
+      weight += w;
+    }
+  }
+
+  return sum / weight;
+}
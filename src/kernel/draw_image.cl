@@ -1,10 +1,45 @@
+/*
+ * Normalized density of the accumulator at pos, shared by the output kernels
+ */
+float tone_map(
+    __global uint * accumulator,
+    __global uint * frequency_max,
+    int2 const pos,
+    uint2 const image_size,
+    uint const de_enabled,
+    float4 const de,
+    float4 const tone
+  )
+{
+  __const float exposure = tone.x;
+  __const float shift = tone.y;
+  __const float gamma = tone.z;
+
+  __const float frequency = de_enabled ?
+    density_estimate(accumulator, pos, image_size, de) :
+    (float)accumulator[pos.y * image_size.x + pos.x];
+  // blurred densities below 1 would make the log negative, a maximum of 1 would divide by 0
+  __const float alpha = frequency_max[0] > 1 ?
+    log(max(frequency, 1.0f)) / log((float)frequency_max[0]) :
+    min(frequency, 1.0f);
+
+  //image[y * size.x + x] = ARGBToUInt32(HSL2ARGB(ARGB2HSL(UInt32ToARGB(palette[colorIndex])) * (float3)(1,1,min(pow(alpha, 1 / gamma), (float)1)))) | 0xFF000000;
+  //image[y * size.x + x] = ARGBToUInt32(UInt32ToARGB(palette[colorIndex]) * min(pow(alpha, 1 / gamma), (float)1)) | 0xFF000000;
+  return clamp(pow(exposure * alpha, 1 / gamma) + shift, 0.0f, 1.0f);
+}
+
 __kernel void draw_image(
     __private uint const preview,
     __global uint * accumulator,
     __write_only image2d_t framebuffer,
     __write_only image2d_t framebuffer_preview,
     __global uint * frequency_max,
-    __private uint const block_id
+    __private uint const block_id,
+    __private uint const de_enabled,
+    __private float4 const de,
+    __private float4 const tone,
+    __private uint const alpha_mode,
+    __private float4 const foreground
   )
 {
   uint x = get_global_id(0);
@@ -22,7 +57,7 @@ __kernel void draw_image(
   uint blocks_y = ceil((float)image_size.y / (float)dimensions.y);
   
   x = x + block_id % blocks_x * dimensions.x;
-  y = y + block_id / blocks_y * dimensions.y;
+  y = y + block_id / blocks_x * dimensions.y;
   
   int2 pos_in;
   int2 pos_out = (int2)(x, y);
@@ -36,28 +71,54 @@ __kernel void draw_image(
     pos_in = (int2)(x, y);
   
   if (
-    (pos_out.x >= image_size.x - 1) || 
-    (pos_out.y >= image_size.y - 1) ||
-    (pos_in.x >= image_size_full.x - 1) ||
-    (pos_in.y >= image_size_full.y - 1)
+    (pos_out.x >= image_size.x) ||
+    (pos_out.y >= image_size.y) ||
+    (pos_in.x >= image_size_full.x) ||
+    (pos_in.y >= image_size_full.y)
   )
     return;
 
-  
-  if(frequency_max[0] > 0){
-    __const float exposure = 1.0f;
-    __const float shift = -0.0f;
-    __const float gamma = 1.0f;
-    
-    __const float frequency = (float)accumulator[pos_in.y * image_size_full.x + pos_in.x];
-    __const float alpha = log(frequency) / log((float)frequency_max[0]);
-    
-    //image[y * size.x + x] = ARGBToUInt32(HSL2ARGB(ARGB2HSL(UInt32ToARGB(palette[colorIndex])) * (float3)(1,1,min(pow(alpha, 1 / gamma), (float)1)))) | 0xFF000000;
-    //image[y * size.x + x] = ARGBToUInt32(UInt32ToARGB(palette[colorIndex]) * min(pow(alpha, 1 / gamma), (float)1)) | 0xFF000000;
-    color pixel = float1ToARGB(min((pow(exposure * alpha, 1 / gamma) + shift), 1.0f));
-    if (preview)
-      write_imageui(framebuffer_preview, pos_out, pixel);
-    else
-      write_imageui(framebuffer, pos_out, pixel);
-  }
+  // every pixel is written, an empty render is black or transparent
+  __const float value = frequency_max[0] > 0 ?
+    tone_map(accumulator, frequency_max, pos_in, image_size_full, de_enabled, de, tone) :
+    0.0f;
+  color pixel;
+  if (preview || alpha_mode == ALPHA_OPAQUE)
+    pixel = float1ToARGB(value);
+  else
+    pixel = convert_uint4(float1ToRGBA(value, alpha_mode, foreground.xyz) * (float)0xFF);
+  if (preview)
+    write_imageui(framebuffer_preview, pos_out, pixel);
+  else
+    write_imageui(framebuffer, pos_out, pixel);
+}
+
+/*
+ * 16 bit per channel output, interleaved RGBA, see `save_image --depth 16`
+ */
+__kernel void draw_image16(
+    __global uint * accumulator,
+    __global ushort * framebuffer,
+    __global uint * frequency_max,
+    __private uint2 const image_size,
+    __private uint const de_enabled,
+    __private float4 const de,
+    __private float4 const tone,
+    __private uint const alpha_mode,
+    __private float4 const foreground
+  )
+{
+  int2 pos = (int2)(get_global_id(0), get_global_id(1));
+  if (pos.x >= image_size.x || pos.y >= image_size.y)
+    return;
+
+  float4 pixel = (float4)(0.0f, 0.0f, 0.0f, alpha_mode == ALPHA_OPAQUE ? 1.0f : 0.0f);
+  if(frequency_max[0] > 0)
+    pixel = float1ToRGBA(
+      tone_map(accumulator, frequency_max, pos, image_size, de_enabled, de, tone),
+      alpha_mode,
+      foreground.xyz
+    );
+
+  vstore4(convert_ushort4(pixel * (float)0xFFFF), pos.y * image_size.x + pos.x, framebuffer);
 }
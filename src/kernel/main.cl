@@ -13,28 +13,35 @@ __constant uint MAX_ORBIT_LENGTH = 1024;
 __constant complex projection_size = (complex)( 3, 3 );
 __constant complex projection_offset = (complex)( -0.5, 0 );
 
-/* screen offset and zoom (crop) */
-__constant complex screen_size = (complex)( 1, 1 );
-__constant complex screen_center = (complex)( 0, 0 );
+/* screen offset and zoom (crop): `view` kernel argument, (center.x, center.y, zoom, -),
+ * the default center is projection_offset, the whole projection window */
 __constant float aspectRatio = 1;
 
 /* Enable atomics with global memory (2x slowdown) */
 __constant bool SyncWrite = true;
 
 #include "kernel/util.cl"
+#include "kernel/density_estimation.cl"
 #include "kernel/draw_image.cl"
 
+/* formula parameters a b c d: `formula` kernel argument of CheckOrbit, see `sweep`.
+ * All zero is the Mandelbrot set */
+#define P_A formula.x
+#define P_B formula.y
+#define P_C formula.z
+#define P_D formula.w
+
 #define init \
   complex z = EPSILON_SMALL;
 
 #define loop \
-  z = c_powr(z, 2) + pixel;
+  z = c_powr(z, 2 + P_A) + pixel * (1 + P_B) + (complex)(P_C, P_D);
 
 
 #define bailout \
   !(isfinite(z.x) & isfinite(z.y))
 
-uint CheckOrbit(complex const pixel){
+uint CheckOrbit(complex const pixel, float4 const formula){
   init;
 
   for(int i = 0; i < MAX_ORBIT_LENGTH; i++){
@@ -54,9 +61,12 @@ uint CheckOrbit(complex const pixel){
 __kernel void main(
     __global uint * accumulator, 
     __global uint * frequency_max,
+    __global ulong * hits,
     __private uint2 const image_size,
     __global __read_only uint * iter,
-    ulong2 random
+    ulong2 random,
+    __private float4 const view,
+    __private float4 const formula
   ) 
 {
   uint id_x = get_global_id(0);
@@ -69,7 +79,7 @@ __kernel void main(
 
   complex pixel = coords_Abnormal2Window(LCPNG(random + (ulong2)gid));
   
-  uint orbit_length  = CheckOrbit(pixel);
+  uint orbit_length  = CheckOrbit(pixel, formula);
 
   if (orbit_length == 0)
     return;
@@ -80,7 +90,7 @@ __kernel void main(
     for(int i = 0; i < orbit_length; i++){
       FORMULA; 
       
-      uint2 coords = coords_Window2Screen((z + screen_center) / screen_size, (complex)(image_size.x, image_size.y));
+      uint2 coords = coords_Window2Screen((z - view.xy) * view.z, (complex)(image_size.x, image_size.y));
       if(coords_testOverflow(coords, image_size)){
         uint index = coords.y * image_size.x + coords.x;
 
@@ -88,12 +98,18 @@ __kernel void main(
         atom_max(&frequency_max[0], accumulator[index]);
       }
     }*/
-    uint2 coords = coords_Window2Screen((pixel + screen_center) / screen_size, (complex)(image_size.x, image_size.y));
+    // coords_Window2Screen removes the projection offset, view.xy lands in the middle of the image
+    uint2 coords = coords_Window2Screen(
+      (pixel - view.xy) * view.z + projection_offset * (complex)(1, -1),
+      (complex)(image_size.x, image_size.y)
+    );
     if(coords_testOverflow(coords, image_size)){
       uint index = coords.y * image_size.x + coords.x;
 
       atom_inc(&accumulator[index]);
       atom_max(&frequency_max[0], accumulator[index]);
+      /* read by `status` instead of summing the accumulator */
+      atom_inc(&hits[0]);
     }
   }
 }
@@ -6,6 +6,26 @@ color float1ToARGB(float pixel){
   return result;
 }
 
+/* save_image --alpha */
+#define ALPHA_OPAQUE 0
+#define ALPHA_STRAIGHT 1
+#define ALPHA_PREMULTIPLIED 2
+
+/*
+ * Normalized density to RGBA in [0, 1], optionally as alpha over a foreground color
+ */
+float4 float1ToRGBA(float pixel, uint alpha_mode, float3 foreground){
+  float value = clamp(pixel, 0.0f, 1.0f);
+  switch(alpha_mode){
+    case ALPHA_STRAIGHT:
+      return (float4)(foreground, value);
+    case ALPHA_PREMULTIPLIED:
+      return (float4)(foreground * value, value);
+    default:
+      return (float4)((float3)(value), 1.0f);
+  }
+}
+
 color UInt32ToARGB(uint pixel){
   color result = (color)0;
   result.w = (pixel & 0xFF) / (float)0xFF;
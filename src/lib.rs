@@ -19,9 +19,58 @@ pub unsafe fn u8_to_u32(mut vec8: Vec<u8>) -> Vec<u32> {
   Vec::from_raw_parts(ptr, length, capacity)
 }
 
+/// FNV-1a, stable across builds and platforms
+pub fn hash64(bytes: &[u8]) -> u64 {
+  let mut hash = 0xcbf29ce484222325u64;
+  for byte in bytes {
+    hash ^= *byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  hash
+}
+
 pub fn debug<F>(f: F)
     where F: FnOnce() {
   if cfg!(debug_assertions) {
     f();
   }
+}
+
+/// Empty directory of one test, unique within the test run and removed on drop
+#[cfg(test)]
+pub struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+  pub fn new(name: &str) -> TempDir {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("{}_test_{}_{}", name, std::process::id(), count));
+    std::fs::create_dir_all(&path).unwrap();
+    TempDir(path)
+  }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+  type Target = std::path::Path;
+
+  fn deref(&self) -> &std::path::Path {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+impl AsRef<std::path::Path> for TempDir {
+  fn as_ref(&self) -> &std::path::Path {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    std::fs::remove_dir_all(&self.0).ok();
+  }
 }
\ No newline at end of file
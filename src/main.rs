@@ -4,8 +4,11 @@ mod lib;
 mod ui;
 mod repl;
 mod opencl;
+mod scene;
+mod batch;
 
 use std::thread;
+use std::path::{Path, PathBuf};
 use std::sync::{mpsc::channel, mpsc::Sender, mpsc::Receiver, Arc, Mutex};
 use image;
 use term_painter::{ToStyle, Color as TColor};
@@ -14,15 +17,74 @@ static mut IMAGE_BUFFER: Option<Arc<Mutex<image::ImageBuffer<image::Rgba<u8>, Ve
 static mut IMAGE_BUFFER_PREVIEW: Option<Arc<Mutex<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>>>> = None;
 static mut TX1: Option<Arc<Mutex<Sender<opencl::Action>>>> = None;
 static mut RX2: Option<Arc<Mutex<Receiver<opencl::ActionResult>>>> = None;
+static mut SESSION: Option<Arc<Mutex<scene::Scene>>> = None; // defaults of the repl commands and ui buttons
 static mut TX3: Option<Arc<Mutex<Sender<orbtk::shell::ShellRequest>>>> = None; // thr_opencl -> orbtk::shell::ShellRequest
 
 fn main() {
+  let matches = clap_app!(opencl_attractor =>
+    (version: env!("CARGO_PKG_VERSION"))
+    (@arg scene: --scene +takes_value "load a scene file (toml, json) on startup")
+    (@arg script: --script +takes_value "run the repl commands of a file, without the ui, and exit")
+    (@arg stdin: --stdin "run repl commands read from stdin, without the ui, and exit")
+    (@subcommand merge =>
+      (about: "sum saved states of the same scene rendered with different seeds, without the ui")
+      (@arg files: +required +multiple "state files")
+      (@arg output: -o --output +takes_value +required "state file, or an image if the extension is one")
+      (@arg force: --force "overwrite an existing image")
+    )
+  ).subcommand(batch::sweep_app()).subcommand(batch::animate_app()).get_matches();
+
+  if let Some(command) = matches.subcommand_matches("merge") {
+    let files = command.values_of("files").unwrap().map(PathBuf::from).collect();
+    let output = PathBuf::from(command.value_of("output").unwrap());
+    if let Err(e) = merge(files, output, command.is_present("force")) {
+      println!("{} {}", TColor::BrightRed.paint("merge::err:"), e);
+      std::process::exit(1);
+    }
+    std::process::exit(0);
+  }
+
+  if let (name @ "sweep", Some(command)) | (name @ "animate", Some(command)) = matches.subcommand() {
+    if let Err(e) = batch_command(name, command, matches.value_of("scene").map(Path::new)) {
+      println!("{} {}", TColor::BrightRed.paint(format!("{}::err:", name)), e);
+      std::process::exit(1);
+    }
+    std::process::exit(0);
+  }
+
+  let options = repl::Options {
+    scene: matches.value_of("scene").map(|x| x.into()),
+    script: matches.value_of("script").map(|x| x.into()),
+    stdin: matches.is_present("stdin")
+  };
+
+  // scripts exit with a non-zero status if a command fails
+  if options.script.is_some() || options.stdin {
+    let (tx1, rx2) = spawn_opencl();
+    repl::init(tx1, rx2, options);
+    std::process::exit(0);
+  }
+
   print!("{}\nType \"help\" for help.\n",
          TColor::BrightRed.paint(
            format!("OpenCL Attractor v{}, gui + repl interface", env!("CARGO_PKG_VERSION"))
          )
   );
 
+  let (tx1, rx2) = spawn_opencl();
+
+  let _thr_repl = thread::spawn( move || {
+    repl::init(tx1, rx2, options);
+  });
+
+  let thr_ui = thread::spawn( || {
+    ui::init();
+  });
+
+  thr_ui.join().unwrap();
+}
+
+fn spawn_opencl() -> (Arc<Mutex<Sender<opencl::Action>>>, Arc<Mutex<Receiver<opencl::ActionResult>>>) {
   // 4-sided rendezvous channel
   let (tx1, rx1) = channel::<opencl::Action>(); // (thr_ui, thr_repl) -> thr_opencl
   let (tx2, rx2) = channel::<opencl::ActionResult>(); // thr_opencl -> (thr_ui, thr_repl)
@@ -35,22 +97,58 @@ fn main() {
     RX2 = Some(rx2.clone());
   }
 
-  let rx2_ref1 = rx2.clone();
-  let _thr_repl = thread::spawn( || {
-    repl::init(tx1, rx2_ref1);
-  });
-
   let _thr_opencl = Some(thread::spawn(||
     opencl::thread(tx2, rx1)
   ));
 
-  let thr_ui = thread::spawn( move || {
-    {
-      let rx2 = rx2.lock().expect("mutex is poisoned");
-      rx2.recv().unwrap(); // wait for opencl init
-    }
-    ui::init();
-  });
+  {
+    let rx2 = rx2.lock().expect("mutex is poisoned");
+    rx2.recv().unwrap(); // wait for opencl init
+  }
+  (tx1, rx2)
+}
 
-  thr_ui.join().unwrap();
+/// Headless `merge`, the device is only needed to tone map an image
+fn merge(files: Vec<PathBuf>, output: PathBuf, force: bool) -> Result<(), String> {
+  if !opencl::is_image_path(&output) {
+    opencl::RenderState::merge(&files)?
+      .save(&output)
+      .map_err(|e| format!("\"{}\": {}", output.display(), e))?;
+    println!("{} {} states merged into \"{}\"", TColor::Green.paint("merge:"), files.len(), output.display());
+    return Ok(());
+  }
+
+  let (tx1, rx2) = spawn_opencl();
+  let tx1 = tx1.lock().expect("mutex is poisoned");
+  let rx2 = rx2.lock().expect("mutex is poisoned");
+  tx1.send(opencl::Action::Merge(files)).unwrap();
+  if !rx2.recv().unwrap().is_ok() {
+    return Err("unable to merge states".into());
+  }
+  tx1.send(opencl::Action::SaveImage(opencl::SaveOptions {
+    output: Some(output),
+    force,
+    ..opencl::SaveOptions::default()
+  })).unwrap();
+  if !rx2.recv().unwrap().is_ok() {
+    return Err("unable to save image".into());
+  }
+  Ok(())
+}
+
+/// Headless `sweep` and `animate`, defaults, base parameters and keyframes from `--scene`
+fn batch_command(name: &str, command: &clap::ArgMatches, scene: Option<&Path>) -> Result<(), String> {
+  let scene = match scene {
+    Some(path) => scene::Scene::load_over(path, &scene::defaults())?,
+    None => scene::defaults()
+  };
+  let options = scene.save_options()?;
+  let (tx1, rx2) = spawn_opencl();
+  let tx1 = tx1.lock().expect("mutex is poisoned");
+  let rx2 = rx2.lock().expect("mutex is poisoned");
+  batch::set_params(&tx1, &rx2, &scene.params)?;
+  match name {
+    "sweep" => batch::Sweep::from_matches(command, &scene)?.run(&tx1, &rx2, &options),
+    _ => batch::Animation::from_matches(command, &scene)?.run(&tx1, &rx2, &scene.keyframes, &options)
+  }
 }
\ No newline at end of file
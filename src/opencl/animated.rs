@@ -0,0 +1,299 @@
+use std::{
+  path::{Path, PathBuf},
+  fs::{self, File},
+  io::{self, Read, Write, BufReader, BufWriter},
+  sync::{Arc, Mutex, MutexGuard},
+  convert::TryInto
+};
+use gif::SetParameter;
+
+/// NeuQuant sampling factor, 1 is the slowest and best
+const GIF_QUANTIZATION_SPEED: i32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AnimatedFormat {
+  /// 256 colors per frame, binary transparency
+  Gif,
+  /// lossless, plays as a still image where unsupported
+  Apng
+}
+
+/// Buffered file shared with an encoder that writes its trailer on drop,
+/// the final flush happens in `finish` where its errors are reported
+#[derive(Clone)]
+struct SharedFile(Arc<Mutex<BufWriter<File>>>);
+
+impl SharedFile {
+  fn create(path: &Path) -> Result<SharedFile, String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    Ok(SharedFile(Arc::new(Mutex::new(BufWriter::new(file)))))
+  }
+
+  fn lock(&self) -> io::Result<MutexGuard<'_, BufWriter<File>>> {
+    self.0.lock().map_err(|_| io::Error::other("file lock is poisoned"))
+  }
+
+  /// Called once the encoder is dropped
+  fn finish(self) -> Result<(), String> {
+    let mut file = self.lock().map_err(|e| e.to_string())?;
+    file.flush().map_err(|e| e.to_string())?;
+    file.get_ref().sync_all().map_err(|e| e.to_string())
+  }
+}
+
+impl Write for SharedFile {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.lock()?.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.lock()?.flush()
+  }
+}
+
+enum Encoder {
+  Gif(gif::Encoder<SharedFile>, SharedFile),
+  /// APNG needs the frame count up front, frames are streamed to a temporary file until `finish`
+  Apng(ApngFrames)
+}
+
+/// Looping animation of RGBA8 frames, shared by `animate` and render timelapses.
+/// Written next to `path` and renamed on `finish`, an aborted animation leaves existing files alone
+pub struct AnimatedWriter {
+  encoder: Encoder,
+  size: (u32, u32),
+  /// per frame, ms
+  delay: u32,
+  path: PathBuf
+}
+
+/// e.g. out.gif.tmp
+fn temporary_path(path: &Path, suffix: &str) -> PathBuf {
+  let mut name = path.file_name().unwrap_or_default().to_os_string();
+  name.push(suffix);
+  path.with_file_name(name)
+}
+
+impl AnimatedWriter {
+  /// Refuses to replace an existing file unless `force`
+  pub fn new(path: &Path, format: AnimatedFormat, size: (u32, u32), delay: u32, force: bool) -> Result<AnimatedWriter, String> {
+    if path.exists() && !force {
+      return Err(format!("\"{}\" already exists, use --force to overwrite", path.display()));
+    }
+    let encoder = match format {
+      AnimatedFormat::Gif => {
+        let width: u16 = size.0.try_into().map_err(|_| "gif frames are at most 65535 px wide".to_string())?;
+        let height: u16 = size.1.try_into().map_err(|_| "gif frames are at most 65535 px high".to_string())?;
+        let file = SharedFile::create(&temporary_path(path, ".tmp"))?;
+        let mut encoder = gif::Encoder::new(file.clone(), width, height, &[]).map_err(|e| e.to_string())?;
+        encoder.set(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+        Encoder::Gif(encoder, file)
+      },
+      AnimatedFormat::Apng => Encoder::Apng(ApngFrames::new(temporary_path(path, ".frames.tmp"))?)
+    };
+    Ok(AnimatedWriter { encoder, size, delay, path: path.into() })
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn add_frame(&mut self, pixels: &[u8]) -> Result<(), String> {
+    if pixels.len() != self.size.0 as usize * self.size.1 as usize * 4 {
+      return Err(format!("frame size differs from the animation size, {}x{}", self.size.0, self.size.1));
+    }
+    match &mut self.encoder {
+      Encoder::Gif(encoder, _) => {
+        let mut pixels = pixels.to_vec();
+        let mut frame = gif::Frame::from_rgba_speed(self.size.0 as u16, self.size.1 as u16, &mut pixels, GIF_QUANTIZATION_SPEED);
+        // 10 ms units
+        frame.delay = ((self.delay + 5) / 10).min(u16::MAX as u32) as u16;
+        // transparent pixels must not show the previous frame
+        frame.dispose = gif::DisposalMethod::Background;
+        encoder.write_frame(&frame).map_err(|e| e.to_string())
+      },
+      Encoder::Apng(frames) => frames.add(self.size, self.delay, &compress_frame(self.size, pixels)?)
+        .map_err(|e| e.to_string())
+    }
+  }
+
+  /// Writes the file trailer and moves the animation into place
+  pub fn finish(self) -> Result<(), String> {
+    let tmp_path = temporary_path(&self.path, ".tmp");
+    match self.encoder {
+      Encoder::Gif(encoder, file) => {
+        // the trailer is written on drop
+        drop(encoder);
+        file.finish()?
+      },
+      Encoder::Apng(frames) => write_apng(&tmp_path, self.size, frames)?
+    }
+    fs::rename(&tmp_path, &self.path).map_err(|e| e.to_string())
+  }
+}
+
+/// fcTL, IDAT and fdAT chunks of the frames as (type, length LE, data) records,
+/// removed on drop
+struct ApngFrames {
+  path: PathBuf,
+  file: BufWriter<File>,
+  count: u32,
+  /// of the next fcTL or fdAT chunk
+  sequence: u32
+}
+
+impl ApngFrames {
+  fn new(path: PathBuf) -> Result<ApngFrames, String> {
+    let file = BufWriter::new(File::create(&path).map_err(|e| e.to_string())?);
+    Ok(ApngFrames { path, file, count: 0, sequence: 0 })
+  }
+
+  fn write_chunk(&mut self, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    self.file.write_all(kind)?;
+    self.file.write_all(&(data.len() as u32).to_le_bytes())?;
+    self.file.write_all(data)
+  }
+
+  fn add(&mut self, size: (u32, u32), delay: u32, data: &[u8]) -> io::Result<()> {
+    let mut fctl = vec![];
+    fctl.extend_from_slice(&self.sequence.to_be_bytes());
+    fctl.extend_from_slice(&size.0.to_be_bytes());
+    fctl.extend_from_slice(&size.1.to_be_bytes());
+    fctl.extend_from_slice(&0u32.to_be_bytes()); // x offset
+    fctl.extend_from_slice(&0u32.to_be_bytes()); // y offset
+    fctl.extend_from_slice(&(delay.min(u16::MAX as u32) as u16).to_be_bytes());
+    fctl.extend_from_slice(&1000u16.to_be_bytes()); // delay in ms
+    fctl.push(0); // dispose: none
+    fctl.push(0); // blend: source, frames replace transparent pixels too
+    self.write_chunk(b"fcTL", &fctl)?;
+    self.sequence += 1;
+
+    // the first frame is the default image, read by decoders without APNG support
+    if self.count == 0 {
+      self.write_chunk(b"IDAT", data)?;
+    } else {
+      let mut fdat = Vec::with_capacity(data.len() + 4);
+      fdat.extend_from_slice(&self.sequence.to_be_bytes());
+      fdat.extend_from_slice(data);
+      self.write_chunk(b"fdAT", &fdat)?;
+      self.sequence += 1;
+    }
+    self.count += 1;
+    Ok(())
+  }
+}
+
+impl Drop for ApngFrames {
+  fn drop(&mut self) {
+    fs::remove_file(&self.path).ok();
+  }
+}
+
+/// Filtered and deflated image data, as in the IDAT chunks of a png
+fn compress_frame(size: (u32, u32), pixels: &[u8]) -> Result<Vec<u8>, String> {
+  let mut png = vec![];
+  {
+    let mut encoder = png::Encoder::new(&mut png, size.0, size.1);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(pixels).map_err(|e| e.to_string())?;
+  }
+
+  // chunks follow the 8 byte signature: length, type, data, crc
+  let mut data = vec![];
+  let mut offset = 8;
+  while offset + 12 <= png.len() {
+    let length = u32::from_be_bytes([png[offset], png[offset + 1], png[offset + 2], png[offset + 3]]) as usize;
+    let start = offset + 8;
+    if &png[offset + 4..start] == b"IDAT" {
+      data.extend_from_slice(&png[start..start + length]);
+    }
+    offset = start + length + 4;
+  }
+  Ok(data)
+}
+
+/// Header and acTL, then the streamed frames, one chunk in memory at a time
+fn write_apng(path: &Path, size: (u32, u32), mut frames: ApngFrames) -> Result<(), String> {
+  if frames.count == 0 {
+    return Err("no frames".into());
+  }
+  frames.file.flush().map_err(|e| e.to_string())?;
+  let mut reader = BufReader::new(File::open(&frames.path).map_err(|e| e.to_string())?);
+
+  let file = SharedFile::create(path)?;
+  let mut encoder = png::Encoder::new(file.clone(), size.0, size.1);
+  encoder.set_color(png::ColorType::RGBA);
+  encoder.set_depth(png::BitDepth::Eight);
+  let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+
+  // frame count, play count (0 loops forever)
+  let mut actl = vec![];
+  actl.extend_from_slice(&frames.count.to_be_bytes());
+  actl.extend_from_slice(&0u32.to_be_bytes());
+  writer.write_chunk(*b"acTL", &actl).map_err(|e| e.to_string())?;
+
+  let mut header = [0u8; 8];
+  loop {
+    match reader.read_exact(&mut header) {
+      Ok(()) => (),
+      Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+      Err(e) => return Err(e.to_string())
+    }
+    let kind = [header[0], header[1], header[2], header[3]];
+    let mut data = vec![0u8; u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize];
+    reader.read_exact(&mut data).map_err(|e| e.to_string())?;
+    writer.write_chunk(kind, &data).map_err(|e| e.to_string())?;
+  }
+  // IEND is written on drop
+  drop(writer);
+  file.finish()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lib::TempDir;
+
+  fn frame(value: u8) -> Vec<u8> {
+    vec![value; 4 * 3 * 4]
+  }
+
+  #[test]
+  fn apng() {
+    let dir = TempDir::new("animated");
+    let path = dir.join("a.apng");
+    for _ in 0..2 {
+      let mut writer = AnimatedWriter::new(&path, AnimatedFormat::Apng, (4, 3), 40, true).unwrap();
+      for value in &[0x10, 0x80, 0xF0] {
+        writer.add_frame(&frame(*value)).unwrap();
+      }
+      writer.finish().unwrap();
+    }
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1, "temporary files are removed");
+
+    let decoder = png::Decoder::new(File::open(&path).unwrap());
+    let (info, mut reader) = decoder.read_info().unwrap();
+    assert_eq!(reader.info().animation_control().map(|x| x.num_frames), Some(3));
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    assert_eq!(pixels, frame(0x10));
+
+    assert!(AnimatedWriter::new(&path, AnimatedFormat::Apng, (4, 3), 40, false).is_err());
+    assert!(AnimatedWriter::new(&path, AnimatedFormat::Gif, (4, 3), 40, false).is_err());
+  }
+
+  #[test]
+  fn gif() {
+    let dir = TempDir::new("animated_gif");
+    let path = dir.join("a.gif");
+    let mut writer = AnimatedWriter::new(&path, AnimatedFormat::Gif, (4, 3), 40, false).unwrap();
+    writer.add_frame(&frame(0x80)).unwrap();
+    assert!(writer.add_frame(&[0; 4]).is_err());
+    writer.finish().unwrap();
+    // trailer
+    assert_eq!(fs::read(&path).unwrap().last(), Some(&0x3b));
+    assert!(!temporary_path(&path, ".tmp").exists());
+  }
+}
\ No newline at end of file
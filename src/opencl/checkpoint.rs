@@ -0,0 +1,235 @@
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  sync::{Arc, atomic::{AtomicUsize, Ordering}, mpsc::{sync_channel, SyncSender}},
+  thread::{self, JoinHandle},
+  time::Duration
+};
+use serde::{Serialize, Deserialize};
+use term_painter::{ToStyle, Color as TColor};
+use super::{RenderState, RenderLimits};
+
+const JOB_FILE: &str = "checkpoint.json";
+/// `render --checkpoint-dir`
+pub const DEFAULT_CHECKPOINT_DIR: &str = "checkpoints";
+/// `render --checkpoint-keep`
+pub const DEFAULT_CHECKPOINT_KEEP: usize = 3;
+
+/// `render --checkpoint-every`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CheckpointOptions {
+  pub dir: PathBuf,
+  /// iterations between checkpoints
+  pub every: u32,
+  /// older checkpoints are deleted
+  pub keep: usize
+}
+
+/// Written next to the checkpoints, so that `resume_checkpoint` knows how far the render should go
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CheckpointJob {
+  /// `randgen_offset` of the finished render, `UNBOUNDED` if only limits end it
+  pub target: u32,
+  pub dimensions: Vec<u32>,
+  pub options: CheckpointOptions,
+  #[serde(default)]
+  pub limits: RenderLimits,
+  /// rendering time up to the newest checkpoint, counted against `limits.time`
+  #[serde(default)]
+  pub elapsed: Duration
+}
+
+impl CheckpointJob {
+  /// Limits of the rest of the render, None if the time limit is used up.
+  /// The sample limit is part of `target`
+  pub fn remaining_limits(&self) -> Option<RenderLimits> {
+    let time = match self.limits.time {
+      Some(time) if time <= self.elapsed => return None,
+      time => time.map(|time| time - self.elapsed)
+    };
+    Some(RenderLimits { time, samples: None, ..self.limits.clone() })
+  }
+
+  pub fn save(&self) -> Result<(), String> {
+    fs::create_dir_all(&self.options.dir).map_err(|e| e.to_string())?;
+    let source = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+    fs::write(self.options.dir.join(JOB_FILE), source).map_err(|e| e.to_string())
+  }
+
+  pub fn load(dir: &Path) -> Result<CheckpointJob, String> {
+    let path = dir.join(JOB_FILE);
+    let source = fs::read_to_string(&path).map_err(|e| format!("\"{}\": {}", path.display(), e))?;
+    serde_json::from_str(&source).map_err(|e| format!("\"{}\": {}", path.display(), e))
+  }
+}
+
+fn checkpoint_path(dir: &Path, randgen_offset: u32) -> PathBuf {
+  dir.join(format!("checkpoint-{:010}.state", randgen_offset))
+}
+
+/// (randgen_offset, path), oldest first
+fn list_checkpoints(dir: &Path) -> Vec<(u32, PathBuf)> {
+  let mut checkpoints = fs::read_dir(dir)
+    .map(|entries| entries
+      .filter_map(|entry| entry.ok())
+      .filter_map(|entry| {
+        let name = entry.file_name().into_string().ok()?;
+        if !name.starts_with("checkpoint-") || !name.ends_with(".state") {
+          return None;
+        }
+        let offset = name["checkpoint-".len()..name.len() - ".state".len()].parse().ok()?;
+        Some((offset, entry.path()))
+      })
+      .collect::<Vec<_>>())
+    .unwrap_or_default();
+  checkpoints.sort();
+  checkpoints
+}
+
+/// Newest checkpoint that loads, a crash during a write leaves at most a stray temporary file
+pub fn latest_checkpoint(dir: &Path) -> Result<PathBuf, String> {
+  for (_, path) in list_checkpoints(dir).into_iter().rev() {
+    match RenderState::load(&path) {
+      Ok(_) => return Ok(path),
+      Err(e) => println!(
+        "{} skipping checkpoint \"{}\": {}",
+        TColor::Yellow.paint("opencl::thr::warn:"), path.display(), e
+      )
+    }
+  }
+  Err(format!("no valid checkpoint in \"{}\"", dir.display()))
+}
+
+/// Saves states on a separate thread, the render loop only enqueues a copy of the accumulator.
+/// The job file follows each checkpoint with the rendering time so far
+pub struct CheckpointWriter {
+  sender: Option<SyncSender<(RenderState, Duration)>>,
+  thread: Option<JoinHandle<()>>,
+  /// states sent and not yet on disk
+  pending: Arc<AtomicUsize>
+}
+
+impl CheckpointWriter {
+  pub fn new(job: &CheckpointJob) -> CheckpointWriter {
+    let (sender, receiver) = sync_channel::<(RenderState, Duration)>(1);
+    let mut job = job.clone();
+    let keep = job.options.keep.max(1);
+    let pending = Arc::new(AtomicUsize::new(0));
+    let written = pending.clone();
+    let thread = thread::spawn(move || {
+      for (state, elapsed) in receiver {
+        let dir = &job.options.dir;
+        let path = checkpoint_path(dir, state.randgen_offset);
+        match state.save(&path) {
+          Ok(()) => {
+            let checkpoints = list_checkpoints(dir);
+            for (_, path) in checkpoints.iter().take(checkpoints.len().saturating_sub(keep)) {
+              fs::remove_file(path).ok();
+            }
+            job.elapsed = elapsed;
+            if let Err(e) = job.save() {
+              println!("{} unable to write checkpoint job: {}", TColor::BrightRed.paint("opencl::thr::err:"), e);
+            }
+          },
+          Err(e) => println!(
+            "{} unable to write checkpoint \"{}\": {}",
+            TColor::BrightRed.paint("opencl::thr::err:"), path.display(), e
+          )
+        }
+        written.fetch_sub(1, Ordering::SeqCst);
+      }
+    });
+    CheckpointWriter { sender: Some(sender), thread: Some(thread), pending }
+  }
+
+  /// Nothing is being written, checked before reading the accumulator for the next checkpoint
+  pub fn is_idle(&self) -> bool {
+    self.pending.load(Ordering::SeqCst) == 0
+  }
+
+  /// Returns false and drops the state if the previous checkpoint is still being written,
+  /// `elapsed` is the rendering time at `state`
+  pub fn try_write(&self, state: RenderState, elapsed: Duration) -> bool {
+    let sender = match &self.sender {
+      Some(sender) => sender,
+      None => return false
+    };
+    self.pending.fetch_add(1, Ordering::SeqCst);
+    if sender.try_send((state, elapsed)).is_err() {
+      self.pending.fetch_sub(1, Ordering::SeqCst);
+      return false;
+    }
+    true
+  }
+
+  /// Blocks until every pending checkpoint is on disk
+  pub fn finish(mut self, state: Option<(RenderState, Duration)>) {
+    if let (Some(sender), Some(state)) = (&self.sender, state) {
+      self.pending.fetch_add(1, Ordering::SeqCst);
+      sender.send(state).ok();
+    }
+    self.sender = None;
+    if let Some(thread) = self.thread.take() {
+      thread.join().ok();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lib::TempDir;
+
+  #[test]
+  fn rotation() {
+    let dir = TempDir::new("checkpoint");
+    let options = CheckpointOptions { dir: dir.to_path_buf(), every: 1, keep: 2 };
+    let job = CheckpointJob {
+      target: 10,
+      dimensions: vec![1],
+      options,
+      limits: RenderLimits { time: Some(Duration::from_secs(60)), ..RenderLimits::default() },
+      elapsed: Duration::default()
+    };
+    job.save().unwrap();
+    let writer = CheckpointWriter::new(&job);
+    let state = |randgen_offset| RenderState {
+      image_size: (1, 1),
+      frequency_max: 0,
+      randgen_offset,
+      seed: 0,
+      source_hash: 0,
+      params: Default::default(),
+      accumulator: vec![0]
+    };
+    for offset in 1..4 {
+      while !writer.is_idle() {
+        thread::yield_now();
+      }
+      assert!(writer.try_write(state(offset), Duration::from_secs(offset as u64)));
+    }
+    writer.finish(Some((state(4), Duration::from_secs(40))));
+    let offsets = list_checkpoints(&dir).into_iter().map(|(offset, _)| offset).collect::<Vec<_>>();
+    assert_eq!(offsets, vec![3, 4]);
+    assert_eq!(latest_checkpoint(&dir).unwrap(), checkpoint_path(&dir, 4));
+    let job = CheckpointJob::load(&dir).unwrap();
+    assert_eq!(job.elapsed, Duration::from_secs(40));
+    assert_eq!(job.remaining_limits().unwrap().time, Some(Duration::from_secs(20)));
+  }
+
+  #[test]
+  fn remaining_limits() {
+    let job = CheckpointJob {
+      target: 10,
+      dimensions: vec![1],
+      options: CheckpointOptions { dir: PathBuf::new(), every: 1, keep: 1 },
+      limits: RenderLimits { time: Some(Duration::from_secs(60)), samples: Some(1000), noise: Some(0.01) },
+      elapsed: Duration::from_secs(60)
+    };
+    assert_eq!(job.remaining_limits(), None);
+    let limits = CheckpointJob { elapsed: Duration::from_secs(15), ..job.clone() }.remaining_limits().unwrap();
+    assert_eq!(limits, RenderLimits { time: Some(Duration::from_secs(45)), samples: None, noise: Some(0.01) });
+    let limits = CheckpointJob { limits: RenderLimits::default(), ..job }.remaining_limits().unwrap();
+    assert!(limits.is_empty());
+  }
+}
\ No newline at end of file
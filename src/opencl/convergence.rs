@@ -0,0 +1,106 @@
+/// previews are compared with one of at least this many times fewer launches,
+/// closer previews differ by little more than their 8-bit rounding
+const MIN_RATIO: f64 = 2.0;
+/// launches between kept previews, bounds their number
+const HISTORY_GROWTH: f64 = 1.25;
+/// mean deviation per pixel that rounding to 8 bits hides, see `Convergence::limited`
+const QUANTIZATION: f64 = 0.5;
+
+/// Noise estimate from tone-mapped previews.
+/// Previews of n1 < n2 launches differ by about sigma * sqrt(1/n1 - 1/n2) per pixel,
+/// while the noise of the later one is sigma / sqrt(n2)
+#[derive(Default)]
+pub struct Convergence {
+  /// launches and RGBA8 pixels of earlier previews, oldest first
+  history: Vec<(u32, Vec<u8>)>,
+  /// relative noise, mean pixel deviation over mean intensity
+  pub estimate: Option<f64>,
+  /// the previews differ by less than their rounding, `estimate` is an upper bound
+  pub limited: bool
+}
+
+fn intensity(pixel: &[u8]) -> f64 {
+  (pixel[0] as f64 + pixel[1] as f64 + pixel[2] as f64) / 3.0
+}
+
+impl Convergence {
+  /// Previews at different parameters are not comparable
+  pub fn reset(&mut self) {
+    self.history.clear();
+    self.estimate = None;
+    self.limited = false;
+  }
+
+  pub fn update(&mut self, launches: u32, pixels: &[u8]) -> Option<f64> {
+    let old_enough = |n: u32| n as f64 * MIN_RATIO <= launches as f64;
+    // the newest preview that is old enough is the reference, older ones are no longer needed
+    while self.history.len() > 1 && old_enough(self.history[1].0) {
+      self.history.remove(0);
+    }
+    if let Some((reference_launches, reference)) = self.history.first() {
+      if old_enough(*reference_launches) && reference.len() == pixels.len() {
+        let (mut deviation, mut sum) = (0.0, 0.0);
+        for (a, b) in reference.chunks(4).zip(pixels.chunks(4)) {
+          let (a, b) = (intensity(a), intensity(b));
+          deviation += (a - b).abs();
+          sum += b;
+        }
+        if sum > 0.0 {
+          let floor = QUANTIZATION * (pixels.len() / 4) as f64;
+          let ratio = launches as f64 / *reference_launches as f64;
+          self.limited = deviation < floor;
+          self.estimate = Some(deviation.max(floor) / sum / (ratio - 1.0).sqrt());
+        }
+      }
+    }
+    if self.history.last().is_some_and(|(_, previous)| previous.len() != pixels.len()) {
+      self.history.clear();
+    }
+    if self.history.last().is_none_or(|(n, _)| launches as f64 >= *n as f64 * HISTORY_GROWTH) {
+      self.history.push((launches, pixels.to_vec()));
+    }
+    self.estimate
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// `value` with every other pixel one step brighter
+  fn preview(value: u8, noisy: bool) -> Vec<u8> {
+    (0..64).flat_map(|i| vec![value + (noisy && i % 2 == 0) as u8; 4]).collect()
+  }
+
+  #[test]
+  fn reference() {
+    let mut convergence = Convergence::default();
+    assert_eq!(convergence.update(10, &preview(100, true)), None);
+    // too close to the first preview
+    assert_eq!(convergence.update(15, &preview(100, false)), None);
+    let estimate = convergence.update(25, &preview(100, false)).unwrap();
+    // compared with the preview at 10 launches, half of the pixels differ by one step
+    assert!((estimate - 0.5 / 100.0 / 1.5f64.sqrt()).abs() < 1e-9);
+    assert!(!convergence.limited);
+    // the preview at 15 launches is old enough now, and the same
+    let estimate = convergence.update(40, &preview(100, false)).unwrap();
+    assert!((estimate - 0.5 / 100.0 / (40.0f64 / 15.0 - 1.0).sqrt()).abs() < 1e-9);
+    assert!(convergence.limited);
+  }
+
+  #[test]
+  fn near_converged() {
+    let mut convergence = Convergence::default();
+    let mut estimates = vec![];
+    // capped preview interval, identical previews
+    for launches in (1..200).map(|x| x * 128) {
+      if let Some(estimate) = convergence.update(launches, &preview(50, false)) {
+        estimates.push(estimate);
+      }
+    }
+    assert!(convergence.limited);
+    assert!(convergence.history.len() <= 6);
+    // never 0, and not inflated by the small growth of the launches between previews
+    assert!(estimates.iter().all(|&x| x > 0.0 && x <= 0.5 / 50.0));
+  }
+}
\ No newline at end of file
@@ -0,0 +1,405 @@
+use std::{
+  str::FromStr,
+  convert::TryFrom,
+  path::{Path, PathBuf},
+  fs::File,
+  io::{BufWriter, Write, Seek}
+};
+use ocl::prm::Float4;
+use serde::{Serialize, Deserialize};
+use super::{Metadata, encode_text_chunk};
+use tiff::encoder::{TiffEncoder, colortype};
+
+/// Alpha channel of the saved image, see `kernel/util.cl`
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlphaMode {
+  /// black background
+  Opaque,
+  /// normalized density as alpha, color channels hold the foreground
+  Straight,
+  /// normalized density as alpha, color channels multiplied by alpha
+  Premultiplied
+}
+
+impl AlphaMode {
+  pub fn name(self) -> &'static str {
+    match self {
+      AlphaMode::Opaque => "opaque",
+      AlphaMode::Straight => "straight",
+      AlphaMode::Premultiplied => "premultiplied"
+    }
+  }
+
+  pub(super) fn as_u32(self) -> u32 {
+    match self {
+      AlphaMode::Opaque => 0,
+      AlphaMode::Straight => 1,
+      AlphaMode::Premultiplied => 2
+    }
+  }
+}
+
+impl FromStr for AlphaMode {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "opaque" => Ok(AlphaMode::Opaque),
+      "straight" => Ok(AlphaMode::Straight),
+      "premultiplied" => Ok(AlphaMode::Premultiplied),
+      _ => Err(format!("unknown alpha mode \"{}\"", s))
+    }
+  }
+}
+
+/// Bits per channel of the saved image, 8 or 16 in scene files
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "u32", into = "u32")]
+pub enum BitDepth {
+  /// framebuffer image, shared with the ui
+  Eight,
+  /// `draw_image16` kernel, tone mapped from the accumulator on save
+  Sixteen
+}
+
+impl BitDepth {
+  pub fn bits(self) -> u32 {
+    match self {
+      BitDepth::Eight => 8,
+      BitDepth::Sixteen => 16
+    }
+  }
+}
+
+impl FromStr for BitDepth {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "8" => Ok(BitDepth::Eight),
+      "16" => Ok(BitDepth::Sixteen),
+      _ => Err(format!("unsupported bit depth \"{}\"", s))
+    }
+  }
+}
+
+impl TryFrom<u32> for BitDepth {
+  type Error = String;
+
+  fn try_from(bits: u32) -> Result<Self, Self::Error> {
+    bits.to_string().parse()
+  }
+}
+
+impl From<BitDepth> for u32 {
+  fn from(depth: BitDepth) -> u32 {
+    depth.bits()
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+  Png,
+  /// 8 bit, no alpha channel
+  #[serde(alias = "jpeg")]
+  Jpg,
+  #[serde(alias = "tif")]
+  Tiff,
+  /// 8 bit
+  Bmp
+}
+
+impl FileFormat {
+  pub fn extension(self) -> &'static str {
+    match self {
+      FileFormat::Png => "png",
+      FileFormat::Jpg => "jpg",
+      FileFormat::Tiff => "tiff",
+      FileFormat::Bmp => "bmp"
+    }
+  }
+
+  fn supports(self, depth: BitDepth, alpha: AlphaMode) -> Result<(), String> {
+    match (self, depth, alpha) {
+      (FileFormat::Jpg, BitDepth::Sixteen, _) | (FileFormat::Bmp, BitDepth::Sixteen, _) =>
+        Err(format!("{} supports 8 bits per channel only", self.extension())),
+      (FileFormat::Jpg, _, AlphaMode::Straight) | (FileFormat::Jpg, _, AlphaMode::Premultiplied) =>
+        Err("jpg has no alpha channel".into()),
+      _ => Ok(())
+    }
+  }
+}
+
+impl FromStr for FileFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_lowercase().as_str() {
+      "png" => Ok(FileFormat::Png),
+      "jpg" | "jpeg" => Ok(FileFormat::Jpg),
+      "tiff" | "tif" => Ok(FileFormat::Tiff),
+      "bmp" => Ok(FileFormat::Bmp),
+      // image 0.22 decodes webp only
+      "webp" => Err("webp is not supported by the image backend, use png or jpg".into()),
+      _ => Err(format!("unsupported image format \"{}\"", s))
+    }
+  }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SaveOptions {
+  pub alpha: AlphaMode,
+  /// RGB, used with transparent alpha modes
+  pub foreground: [u8; 3],
+  pub depth: BitDepth,
+  /// taken from the file extension if omitted, png by default
+  pub format: Option<FileFormat>,
+  /// jpg only, 1..=100
+  pub quality: u8,
+  /// file or directory, may contain template placeholders
+  pub output: Option<PathBuf>,
+  /// file name when `output` is a directory or omitted, see `Template`
+  pub template: String,
+  /// overwrite existing files
+  pub force: bool
+}
+
+impl Default for SaveOptions {
+  fn default() -> Self {
+    SaveOptions {
+      alpha: AlphaMode::Opaque,
+      foreground: [0xFF, 0xFF, 0xFF],
+      depth: BitDepth::Eight,
+      format: None,
+      quality: 90,
+      output: None,
+      template: SaveOptions::DEFAULT_TEMPLATE.into(),
+      force: false
+    }
+  }
+}
+
+impl SaveOptions {
+  pub const DEFAULT_TEMPLATE: &'static str = "opencl_attractor-{timestamp}.{ext}";
+
+  /// Expands the template, infers the format and refuses to overwrite existing files unless `force`
+  pub fn resolve(&self, template: &Template) -> Result<(PathBuf, FileFormat), String> {
+    let raw = match &self.output {
+      Some(output) if !output.is_dir() => output.clone(),
+      Some(output) => output.join(&self.template),
+      None => PathBuf::from(&self.template)
+    };
+    let raw = raw.to_str().ok_or("output path is not valid unicode")?;
+
+    let extension = Path::new(raw).extension().and_then(|x| x.to_str());
+    let format = match (self.format, extension) {
+      (Some(format), _) => format,
+      (None, Some("{ext}")) | (None, None) => FileFormat::Png,
+      (None, Some(extension)) => extension.parse()?
+    };
+    format.supports(self.depth, self.alpha)?;
+    if !(1..=100).contains(&self.quality) {
+      return Err("quality must be in 1..=100".into());
+    }
+
+    let mut path = PathBuf::from(template.expand(raw, format)?);
+    if path.extension().is_none() {
+      path.set_extension(format.extension());
+    }
+    if path.exists() && !self.force {
+      return Err(format!("\"{}\" already exists, use --force to overwrite", path.display()));
+    }
+    Ok((path, format))
+  }
+
+  pub(super) fn foreground_float4(&self) -> Float4 {
+    Float4::new(
+      self.foreground[0] as f32 / 255.0,
+      self.foreground[1] as f32 / 255.0,
+      self.foreground[2] as f32 / 255.0,
+      1.0
+    )
+  }
+}
+
+/// Values of the file name placeholders,
+/// {formula} {width} {height} {iter} {seed} {depth} {timestamp} {ext}
+pub struct Template {
+  /// source hash, see `KernelWrapper::source_hash`
+  pub formula: u64,
+  pub image_size: (u32, u32),
+  pub iterations: u32,
+  pub seed: u64,
+  pub depth: BitDepth,
+  /// unix milliseconds
+  pub timestamp: u128
+}
+
+impl Template {
+  pub fn expand(&self, template: &str, format: FileFormat) -> Result<String, String> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+      result.push_str(&rest[..start]);
+      let end = rest[start..].find('}').ok_or("unclosed \"{\" in file name template")? + start;
+      let value = match &rest[start + 1..end] {
+        "formula" => format!("{:08x}", self.formula >> 32),
+        "width" => self.image_size.0.to_string(),
+        "height" => self.image_size.1.to_string(),
+        "iter" => self.iterations.to_string(),
+        "seed" => self.seed.to_string(),
+        "depth" => self.depth.bits().to_string(),
+        "timestamp" => self.timestamp.to_string(),
+        "ext" => format.extension().to_string(),
+        name => return Err(format!("unknown placeholder \"{{{}}}\" in file name template", name))
+      };
+      result.push_str(&value);
+      rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+  }
+}
+
+/// An image extension, as opposed to a state file.
+/// webp counts as one, saving it reports that it is not supported
+pub fn is_image_path(path: &Path) -> bool {
+  match path.extension().and_then(|x| x.to_str()) {
+    Some(extension) => extension.parse::<FileFormat>().is_ok() || extension.eq_ignore_ascii_case("webp"),
+    None => false
+  }
+}
+
+/// "ff8000" or "#ff8000"
+pub fn parse_color(s: &str) -> Result<[u8; 3], String> {
+  let hex = s.trim_start_matches('#');
+  if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+    return Err(format!("invalid color \"{}\", expected rrggbb", s));
+  }
+  let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+  Ok([channel(0), channel(2), channel(4)])
+}
+
+/// Interleaved RGBA pixels
+pub enum Pixels<'a> {
+  Rgba8(&'a [u8]),
+  Rgba16(&'a [u16])
+}
+
+/// Metadata is embedded into png files only, `quality` applies to jpg
+pub fn write_image(
+  path: &Path,
+  size: (u32, u32),
+  pixels: Pixels,
+  format: FileFormat,
+  quality: u8,
+  metadata: Option<&Metadata>
+) -> Result<(), String> {
+  let file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+  match (format, pixels) {
+    (FileFormat::Png, pixels) => write_png(file, size, pixels, metadata),
+    (FileFormat::Tiff, pixels) => write_tiff(file, size, pixels),
+    (FileFormat::Jpg, Pixels::Rgba8(data)) => write_jpg(file, size, data, quality),
+    (FileFormat::Bmp, Pixels::Rgba8(data)) => write_bmp(file, size, data),
+    (format, Pixels::Rgba16(_)) => Err(format!("{} supports 8 bits per channel only", format.extension()))
+  }
+}
+
+fn write_png<W: Write>(file: W, size: (u32, u32), pixels: Pixels, metadata: Option<&Metadata>) -> Result<(), String> {
+  let mut encoder = png::Encoder::new(file, size.0, size.1);
+  encoder.set_color(png::ColorType::RGBA);
+  let bytes = match pixels {
+    Pixels::Rgba8(data) => {
+      encoder.set_depth(png::BitDepth::Eight);
+      data.to_vec()
+    },
+    Pixels::Rgba16(data) => {
+      // png samples are big endian
+      let mut bytes = Vec::with_capacity(data.len() * 2);
+      for sample in data {
+        bytes.extend_from_slice(&sample.to_be_bytes());
+      }
+      encoder.set_depth(png::BitDepth::Sixteen);
+      bytes
+    }
+  };
+
+  let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+  if let Some(metadata) = metadata {
+    for chunk in metadata.to_text_chunks() {
+      let (kind, data) = encode_text_chunk(&chunk);
+      writer.write_chunk(kind, &data).map_err(|e| e.to_string())?;
+    }
+  }
+  writer.write_image_data(&bytes).map_err(|e| e.to_string())
+}
+
+fn write_tiff<W: Write + Seek>(file: W, size: (u32, u32), pixels: Pixels) -> Result<(), String> {
+  let mut encoder = TiffEncoder::new(file).map_err(|e| e.to_string())?;
+  match pixels {
+    Pixels::Rgba8(data) => encoder.write_image::<colortype::RGBA8>(size.0, size.1, data),
+    Pixels::Rgba16(data) => encoder.write_image::<colortype::RGBA16>(size.0, size.1, data)
+  }.map_err(|e| e.to_string())
+}
+
+fn write_jpg<W: Write>(mut file: W, size: (u32, u32), data: &[u8], quality: u8) -> Result<(), String> {
+  // alpha is dropped by the encoder
+  image::jpeg::JPEGEncoder::new_with_quality(&mut file, quality)
+    .encode(data, size.0, size.1, image::ColorType::RGBA(8))
+    .map_err(|e| e.to_string())?;
+  file.flush().map_err(|e| e.to_string())
+}
+
+fn write_bmp<W: Write>(mut file: W, size: (u32, u32), data: &[u8]) -> Result<(), String> {
+  image::bmp::BMPEncoder::new(&mut file)
+    .encode(data, size.0, size.1, image::ColorType::RGBA(8))
+    .map_err(|e| e.to_string())?;
+  file.flush().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn template() -> Template {
+    Template {
+      formula: 0x0123_4567_89ab_cdef,
+      image_size: (640, 480),
+      iterations: 64,
+      seed: 7,
+      depth: BitDepth::Sixteen,
+      timestamp: 1_500_000_000_000
+    }
+  }
+
+  #[test]
+  fn expand() {
+    let name = template().expand("{formula}-{width}x{height}-{iter}-{seed}-{depth}-{timestamp}.{ext}", FileFormat::Tiff);
+    assert_eq!(name.unwrap(), "01234567-640x480-64-7-16-1500000000000.tiff");
+    assert_eq!(template().expand("plain.png", FileFormat::Png).unwrap(), "plain.png");
+    assert!(template().expand("{unknown}.png", FileFormat::Png).is_err());
+    assert!(template().expand("{width.png", FileFormat::Png).is_err());
+  }
+
+  #[test]
+  fn image_paths() {
+    assert!(is_image_path(Path::new("out/a.PNG")));
+    assert!(is_image_path(Path::new("a.jpeg")));
+    assert!(is_image_path(Path::new("a.webp")));
+    assert!(!is_image_path(Path::new("a.state")));
+    assert!(!is_image_path(Path::new("a")));
+  }
+
+  #[test]
+  fn unsupported_combinations() {
+    let options = SaveOptions { format: Some(FileFormat::Jpg), depth: BitDepth::Sixteen, ..SaveOptions::default() };
+    assert!(options.resolve(&template()).is_err());
+    let options = SaveOptions { format: Some(FileFormat::Jpg), alpha: AlphaMode::Straight, ..SaveOptions::default() };
+    assert!(options.resolve(&template()).is_err());
+    let options = SaveOptions { output: Some("a.webp".into()), ..SaveOptions::default() };
+    assert_eq!(options.resolve(&template()).unwrap_err(), "webp is not supported by the image backend, use png or jpg");
+    assert!("WEBP".parse::<FileFormat>().is_err());
+  }
+}
\ No newline at end of file
@@ -0,0 +1,156 @@
+use std::{fmt, time::Duration};
+use serde::{Serialize, Deserialize};
+
+/// Iteration count of a render bounded by `RenderLimits` only
+pub const UNBOUNDED: u32 = u32::MAX;
+
+/// Stopping conditions of `render` besides the iteration count, the first one reached ends the render
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderLimits {
+  /// rendering time, pauses excluded
+  pub time: Option<Duration>,
+  /// sampled points, one per work item and launch
+  pub samples: Option<u64>,
+  /// relative noise of the preview, see `Convergence`
+  pub noise: Option<f64>
+}
+
+impl RenderLimits {
+  pub fn is_empty(&self) -> bool {
+    *self == RenderLimits::default()
+  }
+
+  /// Launches covering the sample target
+  pub fn launches(&self, samples_per_launch: u64) -> Option<u32> {
+    let per_launch = samples_per_launch.max(1);
+    self.samples.map(|samples| {
+      let launches = samples / per_launch + (samples % per_launch != 0) as u64;
+      launches.min(UNBOUNDED as u64) as u32
+    })
+  }
+
+  /// e.g. "30m, 1e10 samples"
+  pub fn describe(&self) -> String {
+    let mut limits = vec![];
+    if let Some(time) = self.time {
+      limits.push(format_duration(time));
+    }
+    if let Some(samples) = self.samples {
+      limits.push(format!("{:e} samples", samples as f64));
+    }
+    if let Some(noise) = self.noise {
+      limits.push(format!("noise {}", noise));
+    }
+    limits.join(", ")
+  }
+}
+
+/// Condition that ended a render
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StopReason {
+  Iterations,
+  Samples,
+  Time,
+  Noise(/* estimate */ f64),
+  Interrupted,
+  Cancelled,
+  /// kernel launch failed
+  Failed
+}
+
+impl fmt::Display for StopReason {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      StopReason::Iterations => write!(f, "iteration count reached"),
+      StopReason::Samples => write!(f, "sample target reached"),
+      StopReason::Time => write!(f, "time limit reached"),
+      StopReason::Noise(estimate) => write!(f, "noise estimate {:.4} reached", estimate),
+      StopReason::Interrupted => write!(f, "interrupted"),
+      StopReason::Cancelled => write!(f, "cancelled"),
+      StopReason::Failed => write!(f, "kernel launch failed")
+    }
+  }
+}
+
+impl StopReason {
+  /// Ended early by the user or an error, limits are not failures
+  pub fn is_failure(&self) -> bool {
+    matches!(self, StopReason::Interrupted | StopReason::Cancelled | StopReason::Failed)
+  }
+}
+
+/// In the largest unit of `parse_duration` that keeps it whole, e.g. 30m, 90s, 0.25s
+pub fn format_duration(duration: Duration) -> String {
+  let millis = duration.as_millis();
+  for &(unit, scale) in &[("d", 86_400_000), ("h", 3_600_000), ("m", 60_000)] {
+    if millis >= scale && millis.is_multiple_of(scale) {
+      return format!("{}{}", millis / scale, unit);
+    }
+  }
+  format!("{}s", duration.as_secs_f64())
+}
+
+/// e.g. 90, 90s, 30m, 1.5h, 1d
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+  let invalid = || format!("invalid duration \"{}\", expected e.g. 90s, 30m or 2h", value);
+  let (number, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+    Some(i) => (&value[..i], &value[i..]),
+    None => (value, "s")
+  };
+  let scale = match unit {
+    "s" => 1.0,
+    "m" => 60.0,
+    "h" => 3600.0,
+    "d" => 86400.0,
+    _ => return Err(invalid())
+  };
+  match number.parse::<f64>() {
+    Ok(x) if x.is_finite() && x > 0.0 => Ok(Duration::from_millis((x * scale * 1000.0) as u64)),
+    _ => Err(invalid())
+  }
+}
+
+/// e.g. 1e10, 5000000
+pub fn parse_samples(value: &str) -> Result<u64, String> {
+  match value.parse::<f64>() {
+    Ok(x) if (1.0..1.8e19).contains(&x) => Ok(x as u64),
+    _ => Err(format!("invalid sample count \"{}\"", value))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn durations() {
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+    assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+    assert_eq!(parse_duration("0.25s"), Ok(Duration::from_millis(250)));
+    for value in &["", "0", "-5m", "5w", "m", "inf", "1e3s", "NaNh"] {
+      assert!(parse_duration(value).is_err(), "{}", value);
+    }
+    for value in &["90s", "30m", "2h", "1d", "0.25s", "36h"] {
+      assert_eq!(format_duration(parse_duration(value).unwrap()), *value);
+    }
+    let limits = RenderLimits { time: Some(Duration::from_secs(1800)), samples: Some(10_000_000_000), noise: None };
+    assert_eq!(limits.describe(), "30m, 1e10 samples");
+  }
+
+  #[test]
+  fn samples() {
+    assert_eq!(parse_samples("1e10"), Ok(10_000_000_000));
+    assert_eq!(parse_samples("5000000"), Ok(5_000_000));
+    for value in &["0", "0.5", "-1", "1e20", "inf", "many"] {
+      assert!(parse_samples(value).is_err(), "{}", value);
+    }
+    let limits = RenderLimits { samples: Some(1000), ..RenderLimits::default() };
+    assert_eq!(limits.launches(300), Some(4));
+    assert_eq!(limits.launches(0), Some(1000));
+    assert_eq!(RenderLimits::default().launches(300), None);
+  }
+}
\ No newline at end of file
@@ -0,0 +1,147 @@
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
+
+/// per side, as the `width` and `height` settings accept
+pub const MAX_IMAGE_SIZE: u32 = 32768;
+/// RGBA8 preview framebuffer, always 512x512
+const PREVIEW_BYTES: u64 = 512 * 512 * 4;
+/// frequency_max, iter and hits
+const SCALAR_BYTES: u64 = 4 * 2 + 8;
+
+/// Device limits checked before allocating the buffers of an image
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MemoryBudget {
+  /// CL_DEVICE_MAX_MEM_ALLOC_SIZE, bytes
+  pub max_alloc: u64,
+  /// CL_DEVICE_GLOBAL_MEM_SIZE, bytes
+  pub global: u64,
+  /// CL_DEVICE_IMAGE2D_MAX_WIDTH and HEIGHT, px
+  pub image_max: (u32, u32)
+}
+
+fn mib(bytes: u64) -> String {
+  format!("{:.0} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// (largest allocation, total) in bytes of the buffers of `build_buffers`:
+/// accumulator and framebuffer, 4 bytes per pixel each, preview and scalars
+pub fn memory_requirements(image_size: (u32, u32)) -> (u64, u64) {
+  let buffer = image_size.0 as u64 * image_size.1 as u64 * 4;
+  (buffer.max(PREVIEW_BYTES), buffer * 2 + PREVIEW_BYTES + SCALAR_BYTES)
+}
+
+/// Sizes of `new` and loaded images, before any device limit
+pub fn check_image_size(image_size: (u32, u32)) -> Result<(), String> {
+  let valid = 1..=MAX_IMAGE_SIZE;
+  if !valid.contains(&image_size.0) || !valid.contains(&image_size.1) {
+    return Err(format!(
+      "invalid image size {}x{}, width and height must be in 1..={}",
+      image_size.0, image_size.1, MAX_IMAGE_SIZE
+    ));
+  }
+  Ok(())
+}
+
+impl MemoryBudget {
+  pub fn query(device: &ocl::Device) -> ocl::Result<MemoryBudget> {
+    let max_alloc = match device.info(DeviceInfo::MaxMemAllocSize)? {
+      DeviceInfoResult::MaxMemAllocSize(x) => x,
+      _ => u64::MAX
+    };
+    let global = match device.info(DeviceInfo::GlobalMemSize)? {
+      DeviceInfoResult::GlobalMemSize(x) => x,
+      _ => u64::MAX
+    };
+    let width = match device.info(DeviceInfo::Image2dMaxWidth)? {
+      DeviceInfoResult::Image2dMaxWidth(x) => x as u32,
+      _ => u32::MAX
+    };
+    let height = match device.info(DeviceInfo::Image2dMaxHeight)? {
+      DeviceInfoResult::Image2dMaxHeight(x) => x as u32,
+      _ => u32::MAX
+    };
+    Ok(MemoryBudget { max_alloc, global, image_max: (width, height) })
+  }
+
+  /// Rejects sizes the device can't hold, suggesting the largest one of the same aspect ratio
+  pub fn check(&self, image_size: (u32, u32)) -> Result<(), String> {
+    check_image_size(image_size)?;
+    let (largest, total) = memory_requirements(image_size);
+    let mut problems = vec![];
+    if largest > self.max_alloc {
+      problems.push(format!(
+        "{} buffers, the device allocates at most {} at once",
+        mib(largest), mib(self.max_alloc)
+      ));
+    }
+    if total > self.global {
+      problems.push(format!("{} in total, the device has {}", mib(total), mib(self.global)));
+    }
+    if image_size.0 > self.image_max.0 || image_size.1 > self.image_max.1 {
+      problems.push(format!("a framebuffer image of at most {}x{}", self.image_max.0, self.image_max.1));
+    }
+    if problems.is_empty() {
+      return Ok(());
+    }
+    let fit = self.fit(image_size);
+    Err(format!(
+      "{}x{} needs {}, {}x{} fits",
+      image_size.0, image_size.1, problems.join(" and "), fit.0, fit.1
+    ))
+  }
+
+  /// Largest size of the same aspect ratio within the limits, of a size that passed `check_image_size`
+  pub fn fit(&self, image_size: (u32, u32)) -> (u32, u32) {
+    let image_size = (image_size.0.max(1), image_size.1.max(1));
+    let pixels = image_size.0 as f64 * image_size.1 as f64;
+    let fixed = PREVIEW_BYTES + SCALAR_BYTES;
+    let scale = (self.max_alloc as f64 / 4.0 / pixels)
+      .min(self.global.saturating_sub(fixed) as f64 / 8.0 / pixels)
+      .sqrt()
+      .min(self.image_max.0 as f64 / image_size.0 as f64)
+      .min(self.image_max.1 as f64 / image_size.1 as f64)
+      .min(1.0);
+    (
+      ((image_size.0 as f64 * scale).floor() as u32).max(1),
+      ((image_size.1 as f64 * scale).floor() as u32).max(1)
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MIB: u64 = 1024 * 1024;
+
+  #[test]
+  fn fit() {
+    let budget = MemoryBudget { max_alloc: 64 * MIB, global: 100 * MIB, image_max: (16384, 16384) };
+    assert!(budget.check((2048, 2048)).is_ok());
+    assert_eq!(budget.fit((2048, 2048)), (2048, 2048));
+
+    for &size in &[(8192, 8192), (16384, 2048), (1, 30000), (32768, 32768)] {
+      let fit = budget.fit(size);
+      assert!(budget.check(size).unwrap_err().ends_with(&format!("{}x{} fits", fit.0, fit.1)));
+      assert!(budget.check(fit).is_ok(), "{:?} -> {:?}", size, fit);
+      assert!(fit.0 <= size.0 && fit.1 <= size.1);
+    }
+    // global memory bound, two buffers of 4 bytes per pixel
+    let fit = budget.fit((16384, 2048));
+    assert_eq!(fit.0 / fit.1, 8);
+    assert!(fit.0 as u64 * fit.1 as u64 * 8 <= 100 * MIB);
+    // the image limit alone
+    let budget = MemoryBudget { max_alloc: u64::MAX, global: u64::MAX, image_max: (4096, 2048) };
+    assert_eq!(budget.fit((8192, 8192)), (2048, 2048));
+  }
+
+  #[test]
+  fn image_size() {
+    assert!(check_image_size((1, MAX_IMAGE_SIZE)).is_ok());
+    for &size in &[(0, 512), (512, 0), (0, 0), (MAX_IMAGE_SIZE + 1, 1)] {
+      assert!(check_image_size(size).unwrap_err().starts_with("invalid image size"));
+    }
+    let budget = MemoryBudget { max_alloc: 64 * MIB, global: 100 * MIB, image_max: (16384, 16384) };
+    assert!(budget.check((0, 512)).unwrap_err().starts_with("invalid image size"));
+    assert_eq!(budget.fit((0, 0)), (1, 1));
+  }
+}
\ No newline at end of file
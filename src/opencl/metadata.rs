@@ -0,0 +1,193 @@
+use std::{
+  fs::File,
+  io::{self, Read, BufReader},
+  path::Path
+};
+use super::RenderParams;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Everything needed to reproduce a saved image, written as PNG text chunks
+#[derive(Clone, PartialEq, Debug)]
+pub struct Metadata {
+  pub software: String,
+  pub device: String,
+  pub image_size: (u32, u32),
+  pub iterations: u32,
+  /// worker dimensions of the last render
+  pub dimensions: Vec<u32>,
+  pub seed: u64,
+  pub source_hash: u64,
+  /// kernel/main.cl
+  pub formula: String,
+  pub params: RenderParams
+}
+
+/// (keyword, text, international), see PNG specification 11.3.4
+pub type TextChunk = (String, String, bool);
+
+impl Metadata {
+  pub fn to_text_chunks(&self) -> Vec<TextChunk> {
+    let params = serde_json::to_string(&self.params).expect("params are serializable");
+    vec![
+      ("Software".into(), self.software.clone(), false),
+      ("Device".into(), self.device.clone(), true),
+      ("Image size".into(), format!("{} {}", self.image_size.0, self.image_size.1), false),
+      ("Iterations".into(), self.iterations.to_string(), false),
+      ("Worker dimensions".into(), join(&self.dimensions), false),
+      ("Seed".into(), self.seed.to_string(), false),
+      ("Source hash".into(), format!("{:016x}", self.source_hash), false),
+      ("Parameters".into(), params, true),
+      ("Formula".into(), self.formula.clone(), true)
+    ]
+  }
+
+  pub fn from_text_chunks(chunks: &[TextChunk]) -> Result<Metadata, String> {
+    let get = |keyword: &str| chunks
+      .iter()
+      .find(|(k, _, _)| k == keyword)
+      .map(|(_, text, _)| text.as_str())
+      .ok_or_else(|| format!("missing \"{}\", not saved by opencl_attractor", keyword));
+    let invalid = |keyword: &str| format!("invalid \"{}\"", keyword);
+
+    let image_size = split::<u32>(get("Image size")?).ok_or_else(|| invalid("Image size"))?;
+    if image_size.len() != 2 {
+      return Err(invalid("Image size"));
+    }
+
+    Ok(Metadata {
+      software: get("Software")?.to_string(),
+      device: get("Device").unwrap_or_default().to_string(),
+      image_size: (image_size[0], image_size[1]),
+      iterations: get("Iterations")?.parse().map_err(|_| invalid("Iterations"))?,
+      dimensions: split(get("Worker dimensions")?).ok_or_else(|| invalid("Worker dimensions"))?,
+      seed: get("Seed")?.parse().map_err(|_| invalid("Seed"))?,
+      source_hash: u64::from_str_radix(get("Source hash")?, 16).map_err(|_| invalid("Source hash"))?,
+      formula: get("Formula").unwrap_or_default().to_string(),
+      params: serde_json::from_str(get("Parameters")?).map_err(|e| format!("{}: {}", invalid("Parameters"), e))?
+    })
+  }
+
+  pub fn load(path: &Path) -> Result<Metadata, String> {
+    let chunks = read_text_chunks(path).map_err(|e| e.to_string())?;
+    Metadata::from_text_chunks(&chunks)
+  }
+}
+
+fn join(values: &[u32]) -> String {
+  values.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+fn split<T: std::str::FromStr>(s: &str) -> Option<Vec<T>> {
+  s.split_whitespace().map(|x| x.parse().ok()).collect()
+}
+
+/// tEXt or uncompressed iTXt chunk data
+pub fn encode_text_chunk(chunk: &TextChunk) -> ([u8; 4], Vec<u8>) {
+  let (keyword, text, international) = chunk;
+  let mut data = keyword.as_bytes().to_vec();
+  data.push(0);
+  if *international {
+    // compression flag, compression method, language tag, translated keyword
+    data.extend_from_slice(&[0, 0, 0, 0]);
+    data.extend_from_slice(text.as_bytes());
+    (*b"iTXt", data)
+  } else {
+    // latin-1
+    data.extend(text.chars().map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' }));
+    (*b"tEXt", data)
+  }
+}
+
+fn decode_text_chunk(kind: &[u8; 4], data: &[u8]) -> Option<TextChunk> {
+  let separator = data.iter().position(|&x| x == 0)?;
+  let keyword = data[..separator].iter().map(|&x| x as char).collect::<String>();
+  let data = &data[separator + 1..];
+  match kind {
+    b"tEXt" => Some((keyword, data.iter().map(|&x| x as char).collect(), false)),
+    b"iTXt" => {
+      // compressed iTXt is never written by us
+      if data.len() < 2 || data[0] != 0 {
+        return None;
+      }
+      let mut rest = &data[2..];
+      for _ in 0..2 { // language tag, translated keyword
+        let separator = rest.iter().position(|&x| x == 0)?;
+        rest = &rest[separator + 1..];
+      }
+      Some((keyword, String::from_utf8_lossy(rest).into_owned(), true))
+    },
+    _ => None
+  }
+}
+
+pub fn read_text_chunks(path: &Path) -> io::Result<Vec<TextChunk>> {
+  let mut reader = BufReader::new(File::open(path)?);
+  let mut signature = [0u8; 8];
+  reader.read_exact(&mut signature)?;
+  if signature != PNG_SIGNATURE {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "not a png file"));
+  }
+
+  let mut chunks = vec![];
+  loop {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let kind = [header[4], header[5], header[6], header[7]];
+    if &kind == b"IEND" {
+      break;
+    }
+    let mut data = vec![0u8; length + 4]; // + crc
+    reader.read_exact(&mut data)?;
+    if let Some(chunk) = decode_text_chunk(&kind, &data[..length]) {
+      chunks.push(chunk);
+    }
+  }
+  Ok(chunks)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lib::TempDir;
+  use super::super::{write_image, FileFormat, Pixels};
+
+  fn metadata() -> Metadata {
+    let mut params = RenderParams::default();
+    params.view.zoom = 2.5;
+    params.tone.gamma = 2.2;
+    Metadata {
+      software: "opencl_attractor test".into(),
+      device: "Gerät".into(),
+      image_size: (3, 2),
+      iterations: 64,
+      dimensions: vec![512, 512, 2],
+      seed: u64::MAX,
+      source_hash: 0x0123_4567_89ab_cdef,
+      formula: "#define loop \\\n  z = c_powr(z, 2) + pixel;\n".into(),
+      params
+    }
+  }
+
+  #[test]
+  fn text_chunks() {
+    let chunks = metadata().to_text_chunks().iter()
+      .map(|chunk| {
+        let (kind, data) = encode_text_chunk(chunk);
+        decode_text_chunk(&kind, &data).unwrap()
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(Metadata::from_text_chunks(&chunks).unwrap(), metadata());
+    assert!(Metadata::from_text_chunks(&chunks[1..]).unwrap_err().contains("Software"));
+  }
+
+  #[test]
+  fn round_trip() {
+    let dir = TempDir::new("metadata");
+    let path = dir.join("a.png");
+    write_image(&path, (3, 2), Pixels::Rgba8(&[0x80; 3 * 2 * 4]), FileFormat::Png, 0, Some(&metadata())).unwrap();
+    assert_eq!(Metadata::load(&path).unwrap(), metadata());
+  }
+}
\ No newline at end of file
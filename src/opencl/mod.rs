@@ -1,21 +1,44 @@
 mod thread;
+mod params;
+mod export;
+mod state;
+mod metadata;
+mod checkpoint;
+mod animated;
+mod timelapse;
+mod limits;
+mod convergence;
+mod memory;
 
 use std::{
   sync::{Arc, Mutex},
-  collections::HashMap
+  collections::HashMap,
+  mem
 };
-use ocl::{ProQue, Buffer, Image, flags, prm::Uint2, prm::Ulong2, SpatialDims, Queue};
+use ocl::{ProQue, Buffer, Image, Event, flags, prm::Uint2, prm::Ulong2, SpatialDims, Queue};
 use ocl::enums::{ImageChannelOrder, ImageChannelDataType, MemObjectType};
 use term_painter::{ToStyle, Color as TColor};
 use image;
-use crate::lib::debug;
+use crate::lib::{debug, hash64};
 pub use thread::*;
+pub use params::*;
+pub use export::*;
+pub use state::*;
+pub use metadata::*;
+pub use checkpoint::*;
+pub use animated::*;
+pub use timelapse::*;
+pub use limits::*;
+pub use convergence::*;
+pub use memory::*;
 
 struct Args {
   accumulator: Buffer<u32>,
   framebuffer: Image<u8>,
   framebuffer_preview: Image<u8>,
   frequency_max: Buffer<u32>,
+  /// sum of the accumulator
+  hits: Buffer<u64>,
   iter: Buffer<u32>
 }
 
@@ -28,13 +51,24 @@ pub struct KernelWrapper{
   main_que: ProQue,
   kernels: Kernels,
   args: Args,
-  pub image_size: (u32, u32)
+  pub image_size: (u32, u32),
+  /// identifies the formula, see `RenderState`
+  pub source_hash: u64,
+  /// kernel/main.cl at compile time
+  pub formula: String,
+  pub device_name: String,
+  pub budget: MemoryBudget
+}
+
+pub fn load_formula() -> String {
+  std::fs::read_to_string("kernel/main.cl").unwrap_or_default()
 }
 
 pub fn load_source() -> String {
   let files = [
     "kernel/complex.cl",
     "kernel/util.cl",
+    "kernel/density_estimation.cl",
     "kernel/draw_image.cl",
     "kernel/main.cl"
   ];
@@ -64,7 +98,7 @@ fn build_buffers(
     accumulator: Buffer::<u32>::builder()
       .queue(queue.clone())
       .flags(flags::MEM_READ_WRITE)
-      .len(image_size.0 * image_size.1)
+      .len(image_size.0 as usize * image_size.1 as usize)
       .fill_val(0u32)
       .build()?,
     framebuffer: Image::<u8>::builder()
@@ -91,6 +125,12 @@ fn build_buffers(
       .len(1)
       .fill_val(0u32)
       .build()?,
+    hits: Buffer::<u64>::builder()
+      .queue(queue.clone())
+      .flags(flags::MEM_READ_WRITE)
+      .len(1)
+      .fill_val(0u64)
+      .build()?,
     iter: Buffer::<u32>::builder()
       .queue(queue.clone())
       .flags(flags::MEM_READ_ONLY)
@@ -100,16 +140,92 @@ fn build_buffers(
   })
 }
 
-fn build_kernels(que: &ProQue, args: &Args) -> ocl::Result<Kernels> {
+/// Failed `KernelWrapper::resize`
+#[derive(Clone, PartialEq, Debug)]
+pub struct ResizeError {
+  pub message: String,
+  /// the buffers were released and rebuilt, with blank framebuffers and kernels for the new parameters
+  pub restored: bool,
+  /// the accumulator is gone, `image_size` is whatever could be allocated
+  pub lost: bool
+}
+
+/// Accumulator copy in flight, see `KernelWrapper::read_accumulator_async`
+pub struct PendingRead {
+  accumulator: Vec<u32>,
+  frequency_max: Vec<u32>,
+  /// of the accumulator copy, the in-order queue completes frequency_max first
+  event: Event
+}
+
+impl PendingRead {
+  pub fn is_complete(&self) -> ocl::Result<bool> {
+    self.event.is_complete()
+  }
+
+  /// (accumulator, frequency_max), blocks until the copy is done
+  pub fn wait(mut self) -> ocl::Result<(Vec<u32>, u32)> {
+    self.event.wait_for()?;
+    Ok((mem::take(&mut self.accumulator), self.frequency_max[0]))
+  }
+}
+
+impl Drop for PendingRead {
+  fn drop(&mut self) {
+    // the device must not write into freed memory
+    self.event.wait_for().ok();
+  }
+}
+
+fn blank_framebuffers(image_size: (u32, u32)) -> (
+  image::ImageBuffer<image::Rgba<u8>, Vec<u8>>,
+  image::ImageBuffer<image::Rgba<u8>, Vec<u8>>
+) {
+  // exports may be transparent, the preview is always opaque
+  let framebuffer = image::ImageBuffer::from_fn(
+    image_size.0,
+    image_size.1,
+    |_, _|{
+      image::Rgba([0, 0, 0, 0])
+    });
+  let framebuffer_preview = image::ImageBuffer::from_fn(
+    512,
+    512,
+    |_, _|{
+      image::Rgba([0, 0, 0, 0xFF])
+    });
+  (framebuffer, framebuffer_preview)
+}
+
+fn install_framebuffers(
+  framebuffer: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>,
+  framebuffer_preview: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>
+) {
+  unsafe {
+    if let (Some(image_buffer), Some(image_buffer_preview))
+      = (&mut crate::IMAGE_BUFFER, &mut crate::IMAGE_BUFFER_PREVIEW){
+      *image_buffer.lock().expect("mutex is poisoned") = framebuffer;
+      *image_buffer_preview.lock().expect("mutex is poisoned") = framebuffer_preview;
+    } else {
+      crate::IMAGE_BUFFER = Some(Arc::new(Mutex::new(framebuffer)));
+      crate::IMAGE_BUFFER_PREVIEW = Some(Arc::new(Mutex::new(framebuffer_preview)));
+    }
+  }
+}
+
+fn build_kernels(que: &ProQue, args: &Args, params: &RenderParams) -> ocl::Result<Kernels> {
   let image_size = args.framebuffer.dims().to_lens().expect("invalid framebuffer");
 
   Ok(Kernels {
     main: que.kernel_builder("main")
       .arg(&args.accumulator)
       .arg(&args.frequency_max)
+      .arg(&args.hits)
       .arg(Uint2::new(image_size[0] as u32, image_size[1] as u32))
       .arg(&args.iter)
       .arg_named("random", Ulong2::new(0, 0))
+      .arg_named("view", params.view.as_float4())
+      .arg_named("formula", params.formula.as_float4())
       .build()?,
     draw_image: que.kernel_builder("draw_image")
       .global_work_size((512, 512))
@@ -119,12 +235,17 @@ fn build_kernels(que: &ProQue, args: &Args) -> ocl::Result<Kernels> {
       .arg(&args.framebuffer_preview)
       .arg(&args.frequency_max)
       .arg_named("block_id", 0u32)
+      .arg_named("de_enabled", params.density_estimation.enabled as u32)
+      .arg_named("de", params.density_estimation.as_float4())
+      .arg_named("tone", params.tone.as_float4())
+      .arg_named("alpha_mode", AlphaMode::Opaque.as_u32())
+      .arg_named("foreground", SaveOptions::default().foreground_float4())
       .build()?
   })
 }
 
 impl KernelWrapper {
-  pub fn new(image_size: (u32, u32)) -> Result<KernelWrapper, ocl::Error> {
+  pub fn new(image_size: (u32, u32), params: &RenderParams) -> Result<KernelWrapper, ocl::Error> {
 
     let device = ocl::Device::list(
       ocl::Platform::default(), Some(ocl::flags::DEVICE_TYPE_GPU))?
@@ -134,21 +255,13 @@ impl KernelWrapper {
 
     debug(|| println!("{}", TColor::BrightBlack.paint(format!("opencl::device::info: {}", device.to_string()))));
 
-    let framebuffer = image::ImageBuffer::from_fn(
-      image_size.0,
-      image_size.1,
-      |_, _|{
-        image::Rgba([0, 0, 0, 0xFF])
-      });
-    let framebuffer_preview = image::ImageBuffer::from_fn(
-      512,
-      512,
-      |_, _|{
-        image::Rgba([0, 0, 0, 0xFF])
-      });
-
+    let (framebuffer, framebuffer_preview) = blank_framebuffers(image_size);
+    let budget = MemoryBudget::query(&device)?;
+    let device_name = device.name()?;
+    let source = load_source();
+    let source_hash = hash64(source.as_bytes());
     let main_que = ProQue::builder()
-      .src(load_source())
+      .src(source)
       .device(device)
       .dims((512, 512))
       .build()?;
@@ -160,23 +273,76 @@ impl KernelWrapper {
       &framebuffer_preview
     )?;
 
-    let kernels = build_kernels(&main_que, &args)?;
+    let kernels = build_kernels(&main_que, &args, params)?;
+    install_framebuffers(framebuffer, framebuffer_preview);
 
-    unsafe {
-      if let (Some(image_buffer), Some(image_buffer_preview))
-        = (&mut crate::IMAGE_BUFFER, &mut crate::IMAGE_BUFFER_PREVIEW){
-        *image_buffer.lock().expect("mutex is poisoned") = framebuffer;
-        *image_buffer_preview.lock().expect("mutex is poisoned") = framebuffer_preview;
-      } else {
-        crate::IMAGE_BUFFER = Some(Arc::new(Mutex::new(framebuffer)));
-        crate::IMAGE_BUFFER_PREVIEW = Some(Arc::new(Mutex::new(framebuffer_preview)));
+    Ok(KernelWrapper { main_que, kernels, args, image_size, source_hash, formula: load_formula(), device_name, budget })
+  }
+
+  /// Buffers and kernels for another image size, compiled from the current kernel source.
+  /// The accumulator is kept if the device can't hold the new buffers, see `ResizeError`
+  pub fn resize(&mut self, image_size: (u32, u32), params: &RenderParams) -> Result<(), ResizeError> {
+    let kept = |message: String| ResizeError { message, restored: false, lost: false };
+    self.budget.check(image_size).map_err(kept)?;
+
+    // both sets of buffers must fit, otherwise the current ones are released first and restored on failure
+    let saved = if self.device_memory() + memory_requirements(image_size).1 > self.budget.global {
+      let current = (self.image_size, self.read_accumulator().map_err(|e| kept(e.to_string()))?);
+      if let Err(e) = self.allocate((1, 1), params) {
+        return Err(ResizeError { message: e.to_string(), restored: false, lost: true });
+      }
+      Some(current)
+    } else {
+      None
+    };
+    match self.allocate(image_size, params) {
+      Ok(()) => Ok(()),
+      Err(e) => {
+        let message = format!("unable to allocate {}x{}: {}", image_size.0, image_size.1, e);
+        let (current, (accumulator, frequency_max)) = match saved {
+          Some(saved) => saved,
+          None => return Err(kept(message))
+        };
+        match self.allocate(current, params).and_then(|_| self.write_accumulator(&accumulator, frequency_max)) {
+          Ok(()) => Err(ResizeError { message, restored: true, lost: false }),
+          Err(e) => Err(ResizeError {
+            message: format!("{}, unable to restore the previous image: {}", message, e),
+            restored: false,
+            lost: true
+          })
+        }
       }
     }
+  }
+
+  /// Swaps in a new program, buffers and kernels once all of them exist
+  fn allocate(&mut self, image_size: (u32, u32), params: &RenderParams) -> ocl::Result<()> {
+    let source = load_source();
+    let source_hash = hash64(source.as_bytes());
+    let que = ProQue::builder()
+      .src(source)
+      .device(self.main_que.device())
+      .context(self.main_que.context().clone())
+      .dims((512, 512))
+      .build()?;
+
+    let (framebuffer, framebuffer_preview) = blank_framebuffers(image_size);
+    let args = build_buffers(que.queue().clone(), image_size, &framebuffer, &framebuffer_preview)?;
+    // allocation failures may only surface once the buffers are filled
+    que.queue().finish()?;
+    let kernels = build_kernels(&que, &args, params)?;
 
-    Ok(KernelWrapper { main_que, kernels, args, image_size })
+    self.kernels = kernels;
+    self.args = args;
+    self.main_que = que;
+    self.image_size = image_size;
+    self.source_hash = source_hash;
+    self.formula = load_formula();
+    install_framebuffers(framebuffer, framebuffer_preview);
+    Ok(())
   }
 
-  pub fn recompile(&mut self) -> ocl::Result<()>{
+  pub fn recompile(&mut self, params: &RenderParams) -> ocl::Result<()>{
 
     /* Update strategy:
      * 1. compile new Program, migrate Device and Context, build Queue
@@ -185,8 +351,10 @@ impl KernelWrapper {
      * 4. update kernel, program, device, context, and queue references
      */
 
+    let source = load_source();
+    let source_hash = hash64(source.as_bytes());
     let que = ProQue::builder()
-      .src(load_source())
+      .src(source)
       .device(self.main_que.device())
       .context(self.main_que.context().clone())
       .dims((512, 512))
@@ -196,11 +364,68 @@ impl KernelWrapper {
     self.args.framebuffer.set_default_queue(que.queue().clone());
     self.args.framebuffer_preview.set_default_queue(que.queue().clone());
     self.args.frequency_max.set_default_queue(que.queue().clone());
+    self.args.hits.set_default_queue(que.queue().clone());
     self.args.iter.set_default_queue(que.queue().clone());
 
-    self.kernels = build_kernels(&que, &self.args)?;
+    self.kernels = build_kernels(&que, &self.args, params)?;
     self.main_que = que;
+    self.source_hash = source_hash;
+    self.formula = load_formula();
+
+    Ok(())
+  }
+
+  pub fn set_params(&self, params: &RenderParams) -> ocl::Result<()> {
+    self.kernels.draw_image.set_arg("de_enabled", params.density_estimation.enabled as u32)?;
+    self.kernels.draw_image.set_arg("de", params.density_estimation.as_float4())?;
+    self.kernels.draw_image.set_arg("tone", params.tone.as_float4())?;
+    self.kernels.main.set_arg("view", params.view.as_float4())?;
+    self.kernels.main.set_arg("formula", params.formula.as_float4())?;
+    Ok(())
+  }
 
+  /// (accumulator, frequency_max)
+  pub fn read_accumulator(&self) -> ocl::Result<(Vec<u32>, u32)> {
+    let mut accumulator = vec![0u32; self.args.accumulator.len()];
+    let mut frequency_max = vec![0u32; 1];
+    self.args.accumulator.read(&mut accumulator).enq()?;
+    self.args.frequency_max.read(&mut frequency_max).enq()?;
+    Ok((accumulator, frequency_max[0]))
+  }
+
+  /// Enqueues a copy of the accumulator without waiting for it, later launches are queued behind it
+  pub fn read_accumulator_async(&self) -> ocl::Result<PendingRead> {
+    let mut read = PendingRead {
+      accumulator: vec![0u32; self.args.accumulator.len()],
+      frequency_max: vec![0u32; 1],
+      event: Event::empty()
+    };
+    // the vectors are moved into `read` but not reallocated, it waits for the copy before dropping them
+    unsafe {
+      self.args.frequency_max.read(&mut read.frequency_max).block(false).enq()?;
+      self.args.accumulator.read(&mut read.accumulator).block(false).enew(&mut read.event).enq()?;
+    }
+    Ok(read)
+  }
+
+  /// Bytes of device memory held by the buffers
+  pub fn device_memory(&self) -> u64 {
+    memory_requirements(self.image_size).1
+  }
+
+  /// (hits, frequency_max), without reading the accumulator
+  pub fn read_counters(&self) -> ocl::Result<(u64, u32)> {
+    let mut hits = vec![0u64; 1];
+    let mut frequency_max = vec![0u32; 1];
+    self.args.hits.read(&mut hits).enq()?;
+    self.args.frequency_max.read(&mut frequency_max).enq()?;
+    Ok((hits[0], frequency_max[0]))
+  }
+
+  pub fn write_accumulator(&self, accumulator: &[u32], frequency_max: u32) -> ocl::Result<()> {
+    self.args.accumulator.write(accumulator).enq()?;
+    self.args.frequency_max.write(&vec![frequency_max]).enq()?;
+    self.args.hits.write(&vec![accumulator.iter().map(|&x| x as u64).sum::<u64>()]).enq()?;
     Ok(())
   }
 
@@ -213,7 +438,7 @@ impl KernelWrapper {
     Ok(())
   }
 
-  pub fn draw_image(&self) -> ocl::Result<()> {
+  pub fn draw_image(&self, options: &SaveOptions) -> ocl::Result<()> {
     let dimensions;
     match self.kernels.draw_image.default_global_work_size() {
       SpatialDims::Two(d0, d1) => dimensions = (d0, d1),
@@ -227,6 +452,8 @@ impl KernelWrapper {
       (self.image_size.1 as f64 / dimensions.1 as f64).ceil()) as u32;
 
     self.kernels.draw_image.set_arg("preview", false as u32)?;
+    self.kernels.draw_image.set_arg("alpha_mode", options.alpha.as_u32())?;
+    self.kernels.draw_image.set_arg("foreground", options.foreground_float4())?;
     for block_id in 0..blocks_count {
       self.kernels.draw_image.set_arg("block_id", block_id)?;
       unsafe {
@@ -242,6 +469,33 @@ impl KernelWrapper {
     Ok(())
   }
 
+  pub fn draw_image16(&self, params: &RenderParams, options: &SaveOptions) -> ocl::Result<Vec<u16>> {
+    let length = self.image_size.0 as usize * self.image_size.1 as usize * 4;
+    let framebuffer = Buffer::<u16>::builder()
+      .queue(self.main_que.queue().clone())
+      .flags(flags::MEM_WRITE_ONLY | flags::MEM_HOST_READ_ONLY)
+      .len(length)
+      .build()?;
+    let kernel = self.main_que.kernel_builder("draw_image16")
+      .global_work_size(self.image_size)
+      .arg(&self.args.accumulator)
+      .arg(&framebuffer)
+      .arg(&self.args.frequency_max)
+      .arg(Uint2::new(self.image_size.0, self.image_size.1))
+      .arg(params.density_estimation.enabled as u32)
+      .arg(params.density_estimation.as_float4())
+      .arg(params.tone.as_float4())
+      .arg(options.alpha.as_u32())
+      .arg(options.foreground_float4())
+      .build()?;
+    unsafe {
+      kernel.enq()?;
+    }
+    let mut pixels = vec![0u16; length];
+    framebuffer.read(&mut pixels).enq()?;
+    Ok(pixels)
+  }
+
   pub fn draw_image_preview(&self) -> ocl::Result<()> {
     self.kernels.draw_image.set_arg("preview", true as u32)?;
     self.kernels.draw_image.set_arg("block_id", 0u32)?;
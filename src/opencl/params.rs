@@ -0,0 +1,222 @@
+use ocl::prm::Float4;
+use serde::{Serialize, Deserialize};
+
+/// Adaptive gaussian blur between the accumulator and `draw_image`,
+/// radius = max(radius_min, radius_max / (density + 1)^curve)
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DensityEstimation {
+  pub enabled: bool,
+  pub radius_min: f32,
+  pub radius_max: f32,
+  pub curve: f32
+}
+
+impl Default for DensityEstimation {
+  fn default() -> Self {
+    DensityEstimation {
+      enabled: false,
+      radius_min: 0.0,
+      radius_max: 9.0,
+      curve: 0.4
+    }
+  }
+}
+
+impl DensityEstimation {
+  /// kernel limit, see `kernel/density_estimation.cl`
+  pub const RADIUS_LIMIT: f32 = 32.0;
+
+  pub fn validate(&self) -> Result<(), String> {
+    if self.radius_min < 0.0 || self.radius_max < 0.0 {
+      return Err("radius must be non-negative".into());
+    }
+    if self.radius_min > self.radius_max {
+      return Err("minimum radius exceeds maximum radius".into());
+    }
+    if self.radius_max > Self::RADIUS_LIMIT {
+      return Err(format!("maximum radius is limited to {}", Self::RADIUS_LIMIT));
+    }
+    if self.curve < 0.0 {
+      return Err("curve must be non-negative".into());
+    }
+    Ok(())
+  }
+
+  pub(super) fn as_float4(&self) -> Float4 {
+    Float4::new(self.radius_min, self.radius_max, self.curve, 0.0)
+  }
+}
+
+/// Normalized log density to intensity, pow(exposure * density, 1 / gamma) + shift
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToneMapping {
+  pub exposure: f32,
+  pub shift: f32,
+  pub gamma: f32
+}
+
+impl Default for ToneMapping {
+  fn default() -> Self {
+    ToneMapping {
+      exposure: 1.0,
+      shift: 0.0,
+      gamma: 1.0
+    }
+  }
+}
+
+impl ToneMapping {
+  pub fn validate(&self) -> Result<(), String> {
+    // NaN fails too
+    if self.exposure.is_nan() || self.exposure <= 0.0 {
+      return Err("exposure must be positive".into());
+    }
+    if self.gamma.is_nan() || self.gamma <= 0.0 {
+      return Err("gamma must be positive".into());
+    }
+    Ok(())
+  }
+
+  pub(super) fn as_float4(&self) -> Float4 {
+    Float4::new(self.exposure, self.shift, self.gamma, 0.0)
+  }
+}
+
+/// Screen window over the projection, `center` is drawn in the middle of the image
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct View {
+  pub center: [f32; 2],
+  pub zoom: f32
+}
+
+impl Default for View {
+  fn default() -> Self {
+    View {
+      // middle of the sampled window, see `projection_offset` in kernel/main.cl
+      center: [-0.5, 0.0],
+      zoom: 1.0
+    }
+  }
+}
+
+impl View {
+  pub fn validate(&self) -> Result<(), String> {
+    if self.zoom.is_nan() || self.zoom <= 0.0 {
+      return Err("zoom must be positive".into());
+    }
+    if !self.center.iter().all(|x| x.is_finite()) {
+      return Err("center must be finite".into());
+    }
+    Ok(())
+  }
+
+  pub(super) fn as_float4(&self) -> Float4 {
+    Float4::new(self.center[0], self.center[1], self.zoom, 0.0)
+  }
+}
+
+/// Free parameters of the formula, `P_A`..`P_D` in `kernel/main.cl`, all zero is the Mandelbrot set
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormulaParams {
+  pub a: f32,
+  pub b: f32,
+  pub c: f32,
+  pub d: f32
+}
+
+impl FormulaParams {
+  pub(super) fn as_float4(&self) -> Float4 {
+    Float4::new(self.a, self.b, self.c, self.d)
+  }
+}
+
+/// Previews are drawn at growing intervals during a render, every launch at first
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preview {
+  /// interval factor after each preview
+  pub growth: f32,
+  /// launches
+  pub max_interval: u32
+}
+
+impl Default for Preview {
+  fn default() -> Self {
+    Preview {
+      growth: 1.5,
+      max_interval: 128
+    }
+  }
+}
+
+impl Preview {
+  pub fn validate(&self) -> Result<(), String> {
+    if self.growth.is_nan() || self.growth < 1.0 {
+      return Err("preview growth must be at least 1".into());
+    }
+    if self.max_interval == 0 {
+      return Err("preview interval must be positive".into());
+    }
+    Ok(())
+  }
+}
+
+/// Runtime parameters of the kernels, preserved across `New` and `Recompile`
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderParams {
+  pub density_estimation: DensityEstimation,
+  pub tone: ToneMapping,
+  pub view: View,
+  pub formula: FormulaParams,
+  pub preview: Preview
+}
+
+impl RenderParams {
+  /// Scalar parameters addressable by name, e.g. by `sweep`
+  pub const NAMES: &'static [&'static str] = &[
+    "a", "b", "c", "d",
+    "exposure", "shift", "gamma",
+    "center_x", "center_y", "zoom",
+    "de_min", "de_max", "de_curve"
+  ];
+
+  pub fn validate(&self) -> Result<(), String> {
+    self.density_estimation.validate()?;
+    self.tone.validate()?;
+    self.view.validate()?;
+    self.preview.validate()
+  }
+
+  fn field(&mut self, name: &str) -> Result<&mut f32, String> {
+    Ok(match name {
+      "a" => &mut self.formula.a,
+      "b" => &mut self.formula.b,
+      "c" => &mut self.formula.c,
+      "d" => &mut self.formula.d,
+      "exposure" => &mut self.tone.exposure,
+      "shift" => &mut self.tone.shift,
+      "gamma" => &mut self.tone.gamma,
+      "center_x" => &mut self.view.center[0],
+      "center_y" => &mut self.view.center[1],
+      "zoom" => &mut self.view.zoom,
+      "de_min" => &mut self.density_estimation.radius_min,
+      "de_max" => &mut self.density_estimation.radius_max,
+      "de_curve" => &mut self.density_estimation.curve,
+      _ => return Err(format!("unknown parameter \"{}\", expected one of: {}", name, Self::NAMES.join(" ")))
+    })
+  }
+
+  pub fn get(&self, name: &str) -> Result<f32, String> {
+    self.clone().field(name).map(|x| *x)
+  }
+
+  pub fn set(&mut self, name: &str, value: f32) -> Result<(), String> {
+    *self.field(name)? = value;
+    Ok(())
+  }
+}
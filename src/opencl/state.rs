@@ -0,0 +1,247 @@
+use std::{
+  fs::{self, File},
+  io::{self, Read, Write, BufReader, BufWriter},
+  path::{Path, PathBuf}
+};
+use super::{RenderParams, MAX_IMAGE_SIZE};
+
+/* State file layout, little endian:
+ * magic[8], version: u32,
+ * width: u32, height: u32, frequency_max: u32, randgen_offset: u32,
+ * seed: u64, source_hash: u64,
+ * params_length: u32, params: [u8; params_length] (json),
+ * accumulator: [u32; width * height]
+ */
+const MAGIC: &[u8; 8] = b"OCLATTR\0";
+const VERSION: u32 = 1;
+/// bytes before params
+const HEADER_LENGTH: u64 = 48;
+/// serialized parameters are a few hundred bytes
+const MAX_PARAMS_LENGTH: u32 = 1 << 16;
+
+/// Snapshot of a render, enough to continue accumulating exactly where it left off
+#[derive(Clone, PartialEq, Debug)]
+pub struct RenderState {
+  pub image_size: (u32, u32),
+  pub frequency_max: u32,
+  pub randgen_offset: u32,
+  pub seed: u64,
+  /// hash of the kernel source with its includes, see `load_source`.
+  /// Formula parameters and view are part of `params`
+  pub source_hash: u64,
+  pub params: RenderParams,
+  pub accumulator: Vec<u32>
+}
+
+fn invalid_data<E: ToString>(e: E) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+  let mut bytes = [0u8; 4];
+  reader.read_exact(&mut bytes)?;
+  Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+  let mut bytes = [0u8; 8];
+  reader.read_exact(&mut bytes)?;
+  Ok(u64::from_le_bytes(bytes))
+}
+
+impl RenderState {
+  pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    let params = serde_json::to_vec(&self.params).map_err(invalid_data)?;
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&self.image_size.0.to_le_bytes())?;
+    writer.write_all(&self.image_size.1.to_le_bytes())?;
+    writer.write_all(&self.frequency_max.to_le_bytes())?;
+    writer.write_all(&self.randgen_offset.to_le_bytes())?;
+    writer.write_all(&self.seed.to_le_bytes())?;
+    writer.write_all(&self.source_hash.to_le_bytes())?;
+    writer.write_all(&(params.len() as u32).to_le_bytes())?;
+    writer.write_all(&params)?;
+    for value in &self.accumulator {
+      writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()
+  }
+
+  /// Sizes from the header are checked against the limits and, if known, the stream `length`
+  /// before anything is allocated
+  pub fn read<R: Read>(reader: &mut R, length: Option<u64>) -> io::Result<RenderState> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+      return Err(invalid_data("not a state file"));
+    }
+    let version = read_u32(reader)?;
+    if version != VERSION {
+      return Err(invalid_data(format!("unsupported state file version {}", version)));
+    }
+
+    let image_size = (read_u32(reader)?, read_u32(reader)?);
+    let frequency_max = read_u32(reader)?;
+    let randgen_offset = read_u32(reader)?;
+    let seed = read_u64(reader)?;
+    let source_hash = read_u64(reader)?;
+
+    if image_size.0 == 0 || image_size.1 == 0 || image_size.0 > MAX_IMAGE_SIZE || image_size.1 > MAX_IMAGE_SIZE {
+      return Err(invalid_data(format!("invalid image size {}x{}", image_size.0, image_size.1)));
+    }
+    let params_length = read_u32(reader)?;
+    if params_length > MAX_PARAMS_LENGTH {
+      return Err(invalid_data(format!("invalid parameter length {}", params_length)));
+    }
+    let expected = HEADER_LENGTH + params_length as u64 + image_size.0 as u64 * image_size.1 as u64 * 4;
+    if let Some(length) = length.filter(|&x| x < expected) {
+      return Err(invalid_data(format!("truncated state file, {} bytes instead of {}", length, expected)));
+    }
+
+    let mut params = vec![0u8; params_length as usize];
+    reader.read_exact(&mut params)?;
+    let params = serde_json::from_slice(&params).map_err(invalid_data)?;
+
+    let length = image_size.0 as usize * image_size.1 as usize;
+    let mut bytes = vec![0u8; length * 4];
+    reader.read_exact(&mut bytes)?;
+    let accumulator = bytes
+      .chunks_exact(4)
+      .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+      .collect();
+
+    Ok(RenderState { image_size, frequency_max, randgen_offset, seed, source_hash, params, accumulator })
+  }
+
+  /// Written to a temporary file first, an interrupted save never corrupts an existing state
+  pub fn save(&self, path: &Path) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    {
+      let mut writer = BufWriter::new(File::create(&tmp_path)?);
+      self.write(&mut writer)?;
+    }
+    fs::rename(&tmp_path, path)
+  }
+
+  pub fn load(path: &Path) -> io::Result<RenderState> {
+    let file = File::open(path)?;
+    let length = file.metadata()?.len();
+    RenderState::read(&mut BufReader::new(file), Some(length))
+  }
+
+  /// Adds the samples of a separate render of the same scene, e.g. from another machine.
+  /// `randgen_offset` becomes the total, seed and parameters are kept
+  pub fn add(&mut self, other: &RenderState) -> Result<(), String> {
+    if other.image_size != self.image_size {
+      return Err(format!(
+        "image size {}x{} differs from {}x{}",
+        other.image_size.0, other.image_size.1, self.image_size.0, self.image_size.1
+      ));
+    }
+    if other.source_hash != self.source_hash {
+      return Err("rendered with a different kernel source".into());
+    }
+    if other.params.view != self.params.view {
+      return Err("rendered with a different view".into());
+    }
+    if other.params.formula != self.params.formula {
+      return Err("rendered with different formula parameters".into());
+    }
+    for (sum, value) in self.accumulator.iter_mut().zip(&other.accumulator) {
+      *sum = sum.saturating_add(*value);
+    }
+    self.frequency_max = self.accumulator.iter().cloned().max().unwrap_or(0);
+    self.randgen_offset = self.randgen_offset.saturating_add(other.randgen_offset);
+    Ok(())
+  }
+
+  /// States are loaded one at a time, only two accumulators are kept in memory
+  pub fn merge(paths: &[PathBuf]) -> Result<RenderState, String> {
+    let load = |path: &PathBuf| RenderState::load(path).map_err(|e| format!("\"{}\": {}", path.display(), e));
+    let (first, rest) = paths.split_first().ok_or("nothing to merge")?;
+    let mut merged = load(first)?;
+    let mut seeds = vec![merged.seed];
+    for path in rest {
+      let other = load(path)?;
+      // a state continued from another one contains its samples as well
+      if seeds.contains(&other.seed) {
+        return Err(format!("\"{}\": seed {} is used twice, the samples overlap", path.display(), other.seed));
+      }
+      seeds.push(other.seed);
+      merged.add(&other).map_err(|e| format!("\"{}\": {}", path.display(), e))?;
+    }
+    Ok(merged)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn state() -> RenderState {
+    RenderState {
+      image_size: (3, 2),
+      frequency_max: 9,
+      randgen_offset: 42,
+      seed: 7,
+      source_hash: 0xdead_beef,
+      params: RenderParams::default(),
+      accumulator: vec![0, 1, 2, 3, 4, 9]
+    }
+  }
+
+  fn serialize(state: &RenderState) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    state.write(&mut bytes).unwrap();
+    bytes
+  }
+
+  #[test]
+  fn round_trip() {
+    let bytes = serialize(&state());
+    let read = RenderState::read(&mut &bytes[..], Some(bytes.len() as u64)).unwrap();
+    assert_eq!(read, state());
+  }
+
+  #[test]
+  fn truncated() {
+    let bytes = serialize(&state());
+    let short = &bytes[..bytes.len() - 4];
+    assert!(RenderState::read(&mut &short[..], Some(short.len() as u64)).is_err());
+    assert!(RenderState::read(&mut &short[..], None).is_err());
+  }
+
+  #[test]
+  fn oversized_header() {
+    let mut huge = state();
+    huge.image_size = (MAX_IMAGE_SIZE + 1, 1);
+    huge.accumulator.clear();
+    let bytes = serialize(&huge);
+    let error = RenderState::read(&mut &bytes[..], None).unwrap_err();
+    assert!(error.to_string().starts_with("invalid image size"));
+
+    // a plausible size must still fit in the file
+    let mut large = state();
+    large.image_size = (4096, 4096);
+    large.accumulator.clear();
+    let bytes = serialize(&large);
+    let error = RenderState::read(&mut &bytes[..], Some(bytes.len() as u64)).unwrap_err();
+    assert!(error.to_string().starts_with("truncated state file"));
+  }
+
+  #[test]
+  fn add() {
+    let mut sum = state();
+    let mut other = state();
+    other.seed = 8;
+    sum.add(&other).unwrap();
+    assert_eq!(sum.accumulator, vec![0, 2, 4, 6, 8, 18]);
+    assert_eq!((sum.frequency_max, sum.randgen_offset), (18, 84));
+
+    other.params.formula.a = 0.5;
+    assert_eq!(sum.add(&other).unwrap_err(), "rendered with different formula parameters");
+    assert_eq!(sum.accumulator, vec![0, 2, 4, 6, 8, 18]);
+  }
+}
\ No newline at end of file
@@ -1,82 +1,234 @@
 use std::{
+  path::{Path, PathBuf},
   sync::{Arc, Mutex, mpsc::Sender, mpsc::Receiver},
+  collections::VecDeque,
+  cell::RefCell,
   thread::JoinHandle,
   time::{Instant, SystemTime, Duration},
   cmp::min
 };
-use super::KernelWrapper;
+use super::{
+  KernelWrapper, ResizeError, RenderParams, RenderState, Metadata, SaveOptions, Template, BitDepth, Pixels, write_image,
+  CheckpointOptions, CheckpointJob, CheckpointWriter, PendingRead, Timelapse, TimelapseOptions,
+  RenderLimits, StopReason, Convergence, UNBOUNDED
+};
 use term_painter::{ToStyle, Color as TColor};
 use indicatif::{ProgressBar, ProgressStyle};
-use rand::{self, Rng};
+use rand::{self, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use crate::lib::debug;
 
 #[derive(Clone, PartialEq)]
 pub struct ThreadState {
+  /// kernel launches since `New`
   pub randgen_offset: u32,
+  /// per-launch randoms are derived from the seed and launch index, see `launch_random`.
+  /// Same seed, parameters, worker dimensions and device yield a bit-identical accumulator
+  pub seed: u64,
+  /// worker dimensions of the last render
+  pub dimensions: Vec<u32>,
+  pub image_size: (u32, u32),
   pub rendering: bool,
+  /// the running render waits for `Resume`
+  pub paused: bool,
+  pub params: RenderParams,
+  /// samples since `New`, one per work item and launch.
+  /// Estimated from the worker dimensions after `LoadState` and `Merge`
+  pub samples: u64,
+  /// samples that landed in the image, counted by the kernel
+  pub hits: u64,
+  pub frequency_max: u32,
+  /// bytes held by the kernel buffers
+  pub device_memory: u64,
+  /// relative noise of the preview, updated with each preview of a render, see `Convergence`
+  pub noise: Option<f64>,
+  /// running render
+  pub progress: Option<RenderProgress>,
   preview_render_interval: u32
 }
 
+#[derive(Clone, PartialEq, Debug)]
+pub struct RenderProgress {
+  pub job: JobInfo,
+  pub elapsed: Duration,
+  /// samples of this render
+  pub samples: u64,
+  pub limits: RenderLimits,
+  /// the iteration count is only an upper bound, time or noise end the render
+  pub unbounded: bool
+}
+
+impl RenderProgress {
+  /// Share of the render done, the larger of the iterations and the time limit.
+  /// None if only the noise target ends the render
+  pub fn fraction(&self) -> Option<f64> {
+    let iterations = self.job.progress
+      .filter(|_| !self.unbounded)
+      .map(|(done, total)| done as f64 / total.max(1) as f64);
+    let time = self.limits.time.map(|time| self.elapsed.as_secs_f64() / time.as_secs_f64().max(1e-9));
+    [iterations, time].iter()
+      .flatten()
+      .fold(None, |max: Option<f64>, &x| Some(max.map_or(x, |max| max.max(x))))
+      .map(|x| x.min(1.0))
+  }
+}
+
 pub enum Action {
-  New(/* width */ u32, /* height */ u32),
-  Render(/* iterations */ u32, /* dimensions */ Vec<u32>, /* callback */ Option<Box<dyn FnMut() + Send>>),
-  SaveImage,
+  New(/* width */ u32, /* height */ u32, /* seed */ Option<u64>),
+  Render(
+    /* iterations */ u32,
+    /* dimensions */ Vec<u32>,
+    /* seed */ Option<u64>,
+    /* checkpoint */ Option<CheckpointOptions>,
+    /* timelapse */ Option<TimelapseOptions>,
+    /* time, sample and noise limits */ RenderLimits,
+    /* callback, with the reason the render ended */ Option<Box<dyn FnMut(StopReason) + Send>>
+  ),
+  SaveImage(SaveOptions),
+  /// RGBA8 framebuffer, tone mapped as for `SaveImage`
+  GetImage(SaveOptions),
+  SetParams(RenderParams),
+  SaveState(PathBuf),
+  LoadState(PathBuf),
+  /// sum of saved states, continues like `LoadState`
+  Merge(Vec<PathBuf>),
+  LoadParams(PathBuf, /* image size override */ Option<(u32, u32)>),
   GetState,
+  /// stops the render and clears the job queue
   Interrupt,
-  Recompile
+  /// suspends kernel launches of the running render
+  Pause,
+  Resume,
+  Recompile,
+  Jobs,
+  Cancel(/* job id */ u32),
+  Reorder(/* job id */ u32, /* queue position, 0 is next */ usize),
+  /// jobs that failed since the last `Failures`, see `wait`
+  Failures
 }
 
 #[derive(PartialEq)]
 pub enum ActionResult {
   Ok,
   State(ThreadState),
+  /// received during a render, executed after it
+  Queued(/* job id */ u32),
+  /// running render first
+  Jobs(Vec<JobInfo>),
+  Image(/* size */ (u32, u32), Vec<u8>),
+  /// queued jobs that failed and renders that stopped early, see `StopReason::is_failure`
+  Failures(Vec<JobInfo>),
   Err
 }
 
+impl ActionResult {
+  /// Queued actions are not done yet, their outcome is reported by `Failures`
+  pub fn is_ok(&self) -> bool {
+    match self {
+      ActionResult::Ok => true,
+      _ => false
+    }
+  }
+
+  /// Done or queued
+  pub fn is_accepted(&self) -> bool {
+    match self {
+      ActionResult::Ok | ActionResult::Queued(_) => true,
+      _ => false
+    }
+  }
+}
+
+/// Action received during a render
+struct Job {
+  id: u32,
+  action: Action
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct JobInfo {
+  pub id: u32,
+  pub description: String,
+  /// (iterations done, iterations) of the running render
+  pub progress: Option<(u32, u32)>
+}
+
+impl Job {
+  fn info(&self) -> JobInfo {
+    JobInfo { id: self.id, description: describe(&self.action), progress: None }
+  }
+}
+
 pub fn thread(tx2: Arc<Mutex<Sender<ActionResult>>>, rx1: Arc<Mutex<Receiver<Action>>>) -> JoinHandle<()> {
   let mut state = ThreadState {
     randgen_offset: 0u32,
+    seed: rand::random(),
     rendering: false,
+    paused: false,
+    dimensions: vec![512, 512],
+    image_size: (512, 512),
+    params: RenderParams::default(),
+    samples: 0,
+    hits: 0,
+    frequency_max: 0,
+    device_memory: 0,
+    noise: None,
+    progress: None,
     preview_render_interval: 1u32,
   };
 
-  let mut kernel_wrapper = KernelWrapper::new((512, 512)).unwrap();
+  let mut kernel_wrapper = KernelWrapper::new((512, 512), &state.params).unwrap();
   let tx2 = tx2.lock().expect("mutex is poisoned");
   let rx1 = rx1.lock().expect("mutex is poisoned");
   tx2.send(ActionResult::Ok).unwrap();
 
-  let mut rng = rand::thread_rng();
-  let distribution = rand::distributions::Uniform::new_inclusive(0u64, std::u64::MAX);
-  let mut random = [0u64; 2];
+  let mut jobs = VecDeque::<Job>::new();
+  let mut next_job_id = 1u32;
+  // queued jobs have nobody to reply to
+  let failures = RefCell::new(Vec::<JobInfo>::new());
 
   'messages: loop {
-    match rx1.recv().unwrap() {
+    // queued jobs run first, they were answered with `Queued` on arrival
+    let (action, job_id) = match jobs.pop_front() {
+      Some(job) => (job.action, Some(job.id)),
+      None => (rx1.recv().unwrap(), None)
+    };
+    let description = describe(&action);
+    let reply = |result: ActionResult| match job_id {
+      None => tx2.send(result).unwrap(),
+      Some(id) if result == ActionResult::Err => {
+        failures.borrow_mut().push(JobInfo { id, description: description.clone(), progress: None });
+      },
+      Some(_) => ()
+    };
+
+    match action {
 
       /*** New ***/
-      Action::New(width, height) => {
-        state.randgen_offset = 0;
-        state.preview_render_interval = 1;
-        rng = rand::thread_rng();
-        let mut result = ActionResult::Err;
-        match KernelWrapper::new((1, 1)) { // prevent memory overflow
-          Ok(kernel_wrapper_) => {
-            kernel_wrapper = kernel_wrapper_;
-            match KernelWrapper::new((width, height)) {
-              Ok(kernel_wrapper_) => {
-                kernel_wrapper = kernel_wrapper_;
-                redraw_ui();
-                result = ActionResult::Ok;
-              },
-              Err(e) => println!("{}", e)
-            }
+      Action::New(width, height, seed) => {
+        // the current image stays if the new one doesn't fit
+        let params = state.params.clone();
+        match resize(&mut kernel_wrapper, &mut state, (width, height), &params) {
+          Ok(()) => {
+            state.randgen_offset = 0;
+            state.seed = seed.unwrap_or_else(rand::random);
+            state.samples = 0;
+            state.noise = None;
+            state.preview_render_interval = 1;
+            state.image_size = kernel_wrapper.image_size;
+            redraw_ui();
+            reply(ActionResult::Ok);
           },
-          Err(e) => println!("{}", e)
+          Err(e) => {
+            println!("{} {}", TColor::BrightRed.paint("opencl::thr::err:"), e);
+            reply(ActionResult::Err);
+          }
         }
-        tx2.send(result).unwrap();
       },
 
       /*** Render ***/
-      Action::Render(iterations, dimensions, callback) => {
+      Action::Render(iterations, dimensions, seed, checkpoint, timelapse_options, limits, callback) => {
+        let id = job_id.unwrap_or_else(|| next_id(&mut next_job_id));
         let dimm: ocl::SpatialDims;
         match dimensions.len() {
           1 => dimm = (dimensions[0]).into(),
@@ -84,12 +236,58 @@ pub fn thread(tx2: Arc<Mutex<Sender<ActionResult>>>, rx1: Arc<Mutex<Receiver<Act
           3 => dimm = (dimensions[0], dimensions[1], dimensions[2]).into(),
           _ => {
             println!("{} {}", TColor::BrightRed.paint("opencl::thr::err"), "invalid number of dimensions");
-            tx2.send(ActionResult::Err).unwrap();
+            reply(ActionResult::Err);
             continue 'messages;
           }
         };
 
-        tx2.send(ActionResult::Ok).unwrap(); // enqueued
+        if let Some(seed) = seed {
+          state.seed = seed;
+        }
+        state.dimensions = dimensions;
+        let samples_per_launch = work_items(&state.dimensions);
+        let unbounded = iterations == UNBOUNDED;
+        // bounded by time or noise, the sample limit is part of the iteration count
+        let open_ended = unbounded && limits.samples.is_none();
+        let sample_launches = limits.launches(samples_per_launch).unwrap_or(UNBOUNDED);
+        let iterations = min(min(iterations, sample_launches), UNBOUNDED - state.randgen_offset);
+        if unbounded && limits.samples.is_none() && limits.time.is_none() && limits.noise.is_none() {
+          println!("{} {}", TColor::BrightRed.paint("opencl::thr::err:"), "unbounded render without limits");
+          reply(ActionResult::Err);
+          continue 'messages;
+        }
+
+        let checkpoint_writer = match &checkpoint {
+          Some(options) => {
+            let job = CheckpointJob {
+              // the sample limit is part of the iteration count
+              target: if open_ended { UNBOUNDED } else { state.randgen_offset + iterations },
+              dimensions: state.dimensions.clone(),
+              options: options.clone(),
+              limits: limits.clone(),
+              elapsed: Duration::default()
+            };
+            if let Err(e) = job.save() {
+              println!("{} unable to write checkpoint job: {}", TColor::BrightRed.paint("opencl::thr::err:"), e);
+              reply(ActionResult::Err);
+              continue 'messages;
+            }
+            Some(CheckpointWriter::new(&job))
+          },
+          None => None
+        };
+        let mut timelapse = match &timelapse_options {
+          Some(options) => match preview_size().and_then(|size| Timelapse::new(options, size)) {
+            Ok(timelapse) => Some(timelapse),
+            Err(e) => {
+              println!("{} unable to start timelapse, \"{}\": {}", TColor::BrightRed.paint("opencl::thr::err:"), options.path.display(), e);
+              reply(ActionResult::Err);
+              continue 'messages;
+            }
+          },
+          None => None
+        };
+        reply(ActionResult::Ok); // started
 
         debug(|| println!("{} executing OpenCL kernel...", TColor::BrightBlack.paint("opencl::thr:")));
         // fix ProgressBar bug
@@ -97,103 +295,449 @@ pub fn thread(tx2: Arc<Mutex<Sender<ActionResult>>>, rx1: Arc<Mutex<Receiver<Act
         println!();
 
         state.rendering = true;
+        let mut rng = ChaCha8Rng::seed_from_u64(state.seed);
         kernel_wrapper.kernels.main.set_default_global_work_size(dimm);
         let t0 = Instant::now();
         let progress_bar = ProgressBar::new(iterations as u64);
-        progress_bar.set_style(ProgressStyle::default_bar()
-          .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:cyan/blue}] {percent}% iter #{pos} [{eta}]")
-          .progress_chars("##-"));
+        progress_bar.set_style(if open_ended {
+          // a bar would not move
+          ProgressStyle::default_spinner()
+            .template("{spinner:.green} [{elapsed_precise}] iter #{pos} {msg}")
+        } else {
+          ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:cyan/blue}] {percent}% iter #{pos} [{eta}] {msg}")
+            .progress_chars("##-")
+        });
+        let mut convergence = Convergence::default();
+        // the noise target is below what the preview resolves
+        let mut unreachable = false;
+        let mut stop = StopReason::Iterations;
 
+        // checkpoint state waiting for its accumulator copy
+        let mut pending_checkpoint: Option<(RenderState, Duration, PendingRead)> = None;
+        let mut completed = 0;
+        let mut paused_at: Option<Instant> = None;
+        let mut paused_for = Duration::default();
         'render: for iter in 0..iterations {
 
-          // event polling during render
-          if let Ok(message) = rx1.try_recv(){
+          // event polling during render, blocks while paused, parameters still redraw the preview
+          loop {
+            let message = if paused_at.is_some() {
+              rx1.recv().unwrap()
+            } else {
+              match rx1.try_recv() {
+                Ok(message) => message,
+                Err(_) => break
+              }
+            };
             match message {
               Action::GetState => {
+                state.progress = Some(RenderProgress {
+                  job: JobInfo { id, description: description.clone(), progress: Some((completed, iterations)) },
+                  elapsed: active(t0, paused_for, paused_at),
+                  samples: completed as u64 * samples_per_launch,
+                  limits: limits.clone(),
+                  unbounded: open_ended
+                });
+                refresh_stats(&kernel_wrapper, &mut state);
                 tx2.send(ActionResult::State(state.clone())).unwrap();
               },
+              Action::SetParams(params) => {
+                if params.view != state.params.view || params.formula != state.params.formula {
+                  // the accumulator would mix two images, tone mapping and the filter still apply right away
+                  let live = RenderParams { view: state.params.view.clone(), formula: state.params.formula.clone(), ..params.clone() };
+                  set_params(&kernel_wrapper, &mut state, live);
+                  let job = Job { id: next_id(&mut next_job_id), action: Action::SetParams(params) };
+                  progress_bar.println(format!(
+                    "{} job #{} queued: view and formula changes apply after the render",
+                    TColor::Green.paint("opencl::thr:"), job.id
+                  ));
+                  tx2.send(ActionResult::Queued(job.id)).unwrap();
+                  jobs.push_back(job);
+                } else {
+                  tx2.send(set_params(&kernel_wrapper, &mut state, params)).unwrap();
+                }
+                convergence.reset();
+              },
+              Action::Pause => {
+                if paused_at.is_none() {
+                  paused_at = Some(Instant::now());
+                  state.paused = true;
+                  progress_bar.println(format!("{} paused at iteration {}/{}", TColor::Green.paint("opencl::thr:"), completed, iterations));
+                }
+                tx2.send(ActionResult::Ok).unwrap();
+              },
+              Action::Resume => {
+                if let Some(at) = paused_at.take() {
+                  paused_for += at.elapsed();
+                  state.paused = false;
+                  progress_bar.println(format!("{} resumed", TColor::Green.paint("opencl::thr:")));
+                }
+                tx2.send(ActionResult::Ok).unwrap();
+              },
               Action::Interrupt => {
                 progress_bar.finish_and_clear();
                 println!("{} got interrupt signal", TColor::BrightRed.paint("opencl::thr:"));
+                if !jobs.is_empty() {
+                  println!("{} {} queued jobs cancelled", TColor::BrightRed.paint("opencl::thr:"), jobs.len());
+                  failures.borrow_mut().extend(jobs.drain(..).map(|job| job.info()));
+                }
                 tx2.send(ActionResult::Ok).unwrap();
+                stop = StopReason::Interrupted;
                 break 'render;
+              },
+              Action::Jobs => {
+                let current = JobInfo { id, description: description.clone(), progress: Some((completed, iterations)) };
+                let list = std::iter::once(current).chain(jobs.iter().map(Job::info)).collect();
+                tx2.send(ActionResult::Jobs(list)).unwrap();
+              },
+              Action::Cancel(job) if job == id => {
+                progress_bar.finish_and_clear();
+                println!("{} job #{} cancelled", TColor::BrightRed.paint("opencl::thr:"), id);
+                tx2.send(ActionResult::Ok).unwrap();
+                stop = StopReason::Cancelled;
+                break 'render;
+              },
+              Action::Cancel(job) => {
+                tx2.send(cancel_job(&mut jobs, job)).unwrap();
+              },
+              Action::Reorder(job, position) => {
+                tx2.send(reorder_job(&mut jobs, job, position)).unwrap();
+              },
+              Action::Failures => {
+                tx2.send(ActionResult::Failures(failures.replace(vec![]))).unwrap();
+              },
+              action => {
+                let job = Job { id: next_id(&mut next_job_id), action };
+                progress_bar.println(format!("{} job #{} queued: {}", TColor::Green.paint("opencl::thr:"), job.id, describe(&job.action)));
+                tx2.send(ActionResult::Queued(job.id)).unwrap();
+                jobs.push_back(job);
               }
-              _ => ()
             }
           }
 
-          // generate random
-          for x in &mut random {
-            *x = rng.sample(distribution);
+          if limits.time.is_some_and(|time| active(t0, paused_for, paused_at) >= time) {
+            stop = StopReason::Time;
+            break 'render;
           }
 
           // render kernel
-          if let Err(_) = kernel_wrapper.main(iter + state.randgen_offset, (random[0], random[1])){
+          let launch = iter + state.randgen_offset;
+          if kernel_wrapper.main(launch, launch_random(&mut rng, launch)).is_err() {
+            stop = StopReason::Failed;
             break 'render;
           }
+          completed = iter + 1;
+          state.samples += samples_per_launch;
+          if let (Some(writer), Some(options)) = (&checkpoint_writer, &checkpoint) {
+            poll_checkpoint(&mut pending_checkpoint, writer);
+            if completed % options.every == 0 && completed < iterations {
+              if pending_checkpoint.is_some() || !writer.is_idle() {
+                debug(|| println!("{} checkpoint skipped, previous one is still being written", TColor::BrightBlack.paint("opencl::thr:")));
+              } else {
+                match kernel_wrapper.read_accumulator_async() {
+                  Ok(read) => pending_checkpoint = Some((
+                    snapshot_header(&kernel_wrapper, &state, launch + 1), active(t0, paused_for, paused_at), read
+                  )),
+                  Err(e) => println!("{} unable to read checkpoint: {}", TColor::BrightRed.paint("opencl::thr::err:"), e)
+                }
+              }
+            }
+          }
           if iter % state.preview_render_interval == 0 || iter == iterations - 1 {
             kernel_wrapper.draw_image_preview().unwrap();
             redraw_ui();
-            state.preview_render_interval = min((state.preview_render_interval as f32 * 1.5).ceil() as u32, 128);
+            if let Some(capture) = &mut timelapse {
+              if let Err(e) = capture_preview(capture, state.randgen_offset + completed) {
+                progress_bar.println(format!("{} timelapse stopped: {}", TColor::BrightRed.paint("opencl::thr::err:"), e));
+                timelapse = None;
+              }
+            }
+            let preview = &state.params.preview;
+            state.preview_render_interval = min((state.preview_render_interval as f32 * preview.growth).ceil() as u32, preview.max_interval);
+            match measure_noise(&mut convergence, state.randgen_offset + completed) {
+              Ok(Some(estimate)) => {
+                state.noise = Some(estimate);
+                progress_bar.set_message(&format!("noise {:.4}", estimate));
+                if limits.noise.is_some_and(|target| estimate <= target) {
+                  stop = StopReason::Noise(estimate);
+                  break 'render;
+                }
+                if convergence.limited && !unreachable && limits.noise.is_some() {
+                  unreachable = true;
+                  progress_bar.println(format!(
+                    "{} noise target is below the resolution of the 8-bit preview, about {:.4}",
+                    TColor::Yellow.paint("opencl::thr::warn:"), estimate
+                  ));
+                }
+              },
+              Ok(None) => (),
+              Err(e) => progress_bar.println(format!("{} noise estimate: {}", TColor::BrightRed.paint("opencl::thr::err:"), e))
+            }
           }
           progress_bar.inc(1);
         }
         progress_bar.finish_and_clear();
-        state.randgen_offset += iterations;
+        if stop == StopReason::Iterations && completed == sample_launches {
+          stop = StopReason::Samples;
+        }
+        if !limits.is_empty() {
+          match stop {
+            StopReason::Interrupted | StopReason::Cancelled => (),
+            _ => println!(
+              "{} render stopped after {} iterations: {}",
+              TColor::Green.paint("opencl::thr:"), completed, stop
+            )
+          }
+        }
+        if let Some(writer) = checkpoint_writer {
+          // superseded by the final state
+          pending_checkpoint = None;
+          let elapsed = active(t0, paused_for, paused_at);
+          writer.finish(snapshot(&kernel_wrapper, &state, state.randgen_offset + completed).ok().map(|x| (x, elapsed)));
+        }
+        if let (Some(timelapse), Some(options)) = (timelapse, &timelapse_options) {
+          let frames = timelapse.frames;
+          match timelapse.finish() {
+            Ok(()) => println!(
+              "{} timelapse of {} frames saved to \"{}\"",
+              TColor::Green.paint("opencl::thr:"), frames, options.path.display()
+            ),
+            Err(e) => println!(
+              "{} unable to write timelapse, \"{}\": {}",
+              TColor::BrightRed.paint("opencl::thr::err:"), options.path.display(), e
+            )
+          }
+        }
+        // interrupted renders continue where they stopped
+        state.randgen_offset += completed;
 
         state.rendering = false;
+        state.paused = false;
+        state.progress = None;
+        if stop.is_failure() {
+          failures.borrow_mut().push(JobInfo { id, description: description.clone(), progress: Some((completed, iterations)) });
+        }
         if let Some(mut callback) = callback {
-          callback();
+          callback(stop);
         }
 
         debug(|| println!("{} {:?}", TColor::BrightBlack.paint("opencl::render::profiling:"), t0.elapsed()));
       },
 
       /*** SaveImage ***/
-      Action::SaveImage => {
-        kernel_wrapper.draw_image().unwrap();
-
-        unsafe {
-          if let Some(image_buffer) = &crate::IMAGE_BUFFER {
-            let image_buffer = image_buffer.lock().expect("mutex is poisoned");
-            let file_name = format!(
-              "opencl_attractor-{}.png",
-              SystemTime::now().duration_since(
-                SystemTime::UNIX_EPOCH
-              ).unwrap().as_millis()
-            );
-            if let Ok(()) = image_buffer.save(&file_name){
-              println!("{} image saved to \"{}\"", TColor::Green.paint("opencl::thr:"), &file_name);
-              tx2.send(ActionResult::Ok).unwrap();
-            } else {
-              println!("{} unable to save image, \"{}\"", TColor::BrightRed.paint("opencl::thr::err:"), &file_name);
-              tx2.send(ActionResult::Err).unwrap();
+      Action::SaveImage(options) => {
+        let image_size = kernel_wrapper.image_size;
+        let template = Template {
+          formula: kernel_wrapper.source_hash,
+          image_size,
+          iterations: state.randgen_offset,
+          seed: state.seed,
+          depth: options.depth,
+          timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis()
+        };
+        let (path, format) = match options.resolve(&template) {
+          Ok(resolved) => resolved,
+          Err(e) => {
+            println!("{} unable to save image: {}", TColor::BrightRed.paint("opencl::thr::err:"), e);
+            reply(ActionResult::Err);
+            continue 'messages;
+          }
+        };
+        let metadata = Metadata {
+          software: format!("opencl_attractor {}", env!("CARGO_PKG_VERSION")),
+          device: kernel_wrapper.device_name.clone(),
+          image_size,
+          iterations: state.randgen_offset,
+          dimensions: state.dimensions.clone(),
+          seed: state.seed,
+          source_hash: kernel_wrapper.source_hash,
+          formula: kernel_wrapper.formula.clone(),
+          params: state.params.clone()
+        };
+
+        let result = match options.depth {
+          BitDepth::Eight => {
+            kernel_wrapper.draw_image(&options).unwrap();
+            unsafe {
+              match &crate::IMAGE_BUFFER {
+                Some(image_buffer) => {
+                  let image_buffer = image_buffer.lock().expect("mutex is poisoned");
+                  write_image(&path, image_size, Pixels::Rgba8(&image_buffer), format, options.quality, Some(&metadata))
+                },
+                None => Err("framebuffer is not initialized".to_string())
+              }
             }
+          },
+          BitDepth::Sixteen => kernel_wrapper.draw_image16(&state.params, &options)
+            .map_err(|e| e.to_string())
+            .and_then(|pixels|
+              write_image(&path, image_size, Pixels::Rgba16(&pixels), format, options.quality, Some(&metadata))
+            )
+        };
+
+        match result {
+          Ok(()) => {
+            println!("{} image saved to \"{}\"", TColor::Green.paint("opencl::thr:"), path.display());
+            reply(ActionResult::Ok);
+          },
+          Err(e) => {
+            println!("{} unable to save image, \"{}\": {}", TColor::BrightRed.paint("opencl::thr::err:"), path.display(), e);
+            reply(ActionResult::Err);
+          }
+        }
+      },
+
+      /*** GetImage ***/
+      Action::GetImage(options) => {
+        let result = kernel_wrapper.draw_image(&options).map_err(|e| e.to_string()).and_then(|_| unsafe {
+          match &crate::IMAGE_BUFFER {
+            Some(image_buffer) => Ok(image_buffer.lock().expect("mutex is poisoned").clone().into_raw()),
+            None => Err("framebuffer is not initialized".to_string())
+          }
+        });
+        match result {
+          Ok(pixels) => reply(ActionResult::Image(kernel_wrapper.image_size, pixels)),
+          Err(e) => {
+            println!("{} unable to read image: {}", TColor::BrightRed.paint("opencl::thr::err:"), e);
+            reply(ActionResult::Err);
+          }
+        }
+      },
+
+      /*** SetParams ***/
+      Action::SetParams(params) => {
+        reply(set_params(&kernel_wrapper, &mut state, params));
+      },
+
+      /*** SaveState ***/
+      Action::SaveState(path) => {
+        let result = snapshot(&kernel_wrapper, &state, state.randgen_offset)
+          .and_then(|render_state| render_state.save(&path).map_err(|e| e.to_string()));
+
+        match result {
+          Ok(()) => {
+            println!("{} state saved to \"{}\"", TColor::Green.paint("opencl::thr:"), path.display());
+            reply(ActionResult::Ok);
+          },
+          Err(e) => {
+            println!("{} unable to save state, \"{}\": {}", TColor::BrightRed.paint("opencl::thr::err:"), path.display(), e);
+            reply(ActionResult::Err);
+          }
+        }
+      },
+
+      /*** LoadState ***/
+      Action::LoadState(path) => {
+        match load_state(&mut kernel_wrapper, &mut state, &path) {
+          Ok(()) => {
+            println!(
+              "{} state loaded from \"{}\", {}x{}, {} iterations",
+              TColor::Green.paint("opencl::thr:"),
+              path.display(), kernel_wrapper.image_size.0, kernel_wrapper.image_size.1, state.randgen_offset
+            );
+            kernel_wrapper.draw_image_preview().unwrap();
+            redraw_ui();
+            reply(ActionResult::Ok);
+          },
+          Err(e) => {
+            println!("{} unable to load state, \"{}\": {}", TColor::BrightRed.paint("opencl::thr::err:"), path.display(), e);
+            reply(ActionResult::Err);
+          }
+        }
+      },
+
+      /*** Merge ***/
+      Action::Merge(paths) => {
+        let result = RenderState::merge(&paths)
+          .and_then(|render_state| apply_state(&mut kernel_wrapper, &mut state, render_state));
+        match result {
+          Ok(()) => {
+            println!(
+              "{} {} states merged, {}x{}, {} iterations",
+              TColor::Green.paint("opencl::thr:"),
+              paths.len(), kernel_wrapper.image_size.0, kernel_wrapper.image_size.1, state.randgen_offset
+            );
+            kernel_wrapper.draw_image_preview().unwrap();
+            redraw_ui();
+            reply(ActionResult::Ok);
+          },
+          Err(e) => {
+            println!("{} unable to merge states: {}", TColor::BrightRed.paint("opencl::thr::err:"), e);
+            reply(ActionResult::Err);
+          }
+        }
+      },
+
+      /*** LoadParams ***/
+      Action::LoadParams(path, image_size) => {
+        match load_params(&mut kernel_wrapper, &mut state, &path, image_size) {
+          Ok(iterations) => {
+            println!(
+              "{} parameters loaded from \"{}\", {}x{}, re-render with `render -i {} -d {}`",
+              TColor::Green.paint("opencl::thr:"),
+              path.display(), kernel_wrapper.image_size.0, kernel_wrapper.image_size.1,
+              iterations, state.dimensions.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")
+            );
+            kernel_wrapper.draw_image_preview().unwrap();
+            redraw_ui();
+            reply(ActionResult::Ok);
+          },
+          Err(e) => {
+            println!("{} unable to load parameters, \"{}\": {}", TColor::BrightRed.paint("opencl::thr::err:"), path.display(), e);
+            reply(ActionResult::Err);
           }
         }
       },
 
       /*** GetState ***/
       Action::GetState => {
-        tx2.send(ActionResult::State(state.clone())).unwrap();
+        refresh_stats(&kernel_wrapper, &mut state);
+        reply(ActionResult::State(state.clone()));
       },
 
       /*** Interrupt ***/
       Action::Interrupt => {
-        tx2.send(ActionResult::Ok).unwrap();
+        reply(ActionResult::Ok);
+      },
+
+      /*** Pause, Resume ***/
+      Action::Pause | Action::Resume => {
+        println!("{} not rendering", TColor::BrightRed.paint("opencl::thr::err:"));
+        reply(ActionResult::Err);
+      },
+
+      /*** Jobs ***/
+      Action::Jobs => {
+        reply(ActionResult::Jobs(jobs.iter().map(Job::info).collect()));
+      },
+
+      /*** Cancel ***/
+      Action::Cancel(id) => {
+        reply(cancel_job(&mut jobs, id));
+      },
+
+      /*** Reorder ***/
+      Action::Reorder(id, position) => {
+        reply(reorder_job(&mut jobs, id, position));
+      },
+
+      /*** Failures ***/
+      Action::Failures => {
+        reply(ActionResult::Failures(failures.replace(vec![])));
       },
 
       /*** Recompile ***/
       Action::Recompile => {
-        match kernel_wrapper.recompile() {
+        match kernel_wrapper.recompile(&state.params) {
           Ok(()) => {
             kernel_wrapper.draw_image_preview().unwrap();
             redraw_ui();
-            tx2.send(ActionResult::Ok).unwrap();
+            reply(ActionResult::Ok);
           },
           Err(e) => {
             println!("{}", e);
-            tx2.send(ActionResult::Err).unwrap();
+            reply(ActionResult::Err);
           }
         }
       }
@@ -221,6 +765,278 @@ pub fn thread_interrupt(tx1: Arc<Mutex<Sender<Action>>>, rx2: Arc<Mutex<Receiver
   return false;
 }
 
+fn next_id(next_job_id: &mut u32) -> u32 {
+  *next_job_id += 1;
+  *next_job_id - 1
+}
+
+fn cancel_job(jobs: &mut VecDeque<Job>, id: u32) -> ActionResult {
+  match jobs.iter().position(|job| job.id == id) {
+    Some(index) => {
+      jobs.remove(index);
+      println!("{} job #{} cancelled", TColor::BrightRed.paint("opencl::thr:"), id);
+      ActionResult::Ok
+    },
+    None => {
+      println!("{} no job #{}", TColor::BrightRed.paint("opencl::thr::err:"), id);
+      ActionResult::Err
+    }
+  }
+}
+
+fn reorder_job(jobs: &mut VecDeque<Job>, id: u32, position: usize) -> ActionResult {
+  match jobs.iter().position(|job| job.id == id).and_then(|index| jobs.remove(index)) {
+    Some(job) => {
+      jobs.insert(min(position, jobs.len()), job);
+      ActionResult::Ok
+    },
+    None => {
+      println!("{} no queued job #{}", TColor::BrightRed.paint("opencl::thr::err:"), id);
+      ActionResult::Err
+    }
+  }
+}
+
+fn describe(action: &Action) -> String {
+  match action {
+    Action::New(width, height, _) => format!("new {}x{}", width, height),
+    Action::Render(iterations, dimensions, _, _, _, limits, _) => {
+      let dimensions = dimensions.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ");
+      match (*iterations, limits.is_empty()) {
+        (UNBOUNDED, _) => format!("render until {}, dimensions {}", limits.describe(), dimensions),
+        (_, true) => format!("render {} iterations, dimensions {}", iterations, dimensions),
+        (_, false) => format!("render {} iterations or {}, dimensions {}", iterations, limits.describe(), dimensions)
+      }
+    },
+    Action::SaveImage(options) => match &options.output {
+      Some(output) => format!("save_image \"{}\"", output.display()),
+      None => "save_image".into()
+    },
+    Action::GetImage(_) => "read image".into(),
+    Action::SetParams(_) => "set parameters".into(),
+    Action::SaveState(path) => format!("save_state \"{}\"", path.display()),
+    Action::LoadState(path) => format!("load_state \"{}\"", path.display()),
+    Action::Merge(paths) => format!("merge {} states", paths.len()),
+    Action::LoadParams(path, _) => format!("load_params \"{}\"", path.display()),
+    Action::GetState => "status".into(),
+    Action::Interrupt => "interrupt".into(),
+    Action::Pause => "pause".into(),
+    Action::Resume => "resume".into(),
+    Action::Recompile => "recompile".into(),
+    Action::Jobs => "jobs".into(),
+    Action::Cancel(id) => format!("cancel #{}", id),
+    Action::Reorder(id, position) => format!("reorder #{} {}", id, position),
+    Action::Failures => "failures".into()
+  }
+}
+
+fn preview_size() -> Result<(u32, u32), String> {
+  unsafe {
+    match &crate::IMAGE_BUFFER_PREVIEW {
+      Some(image_buffer) => Ok(image_buffer.lock().expect("mutex is poisoned").dimensions()),
+      None => Err("preview framebuffer is not initialized".into())
+    }
+  }
+}
+
+/// Convergence of the preview, drawn at `launches`
+fn measure_noise(convergence: &mut Convergence, launches: u32) -> Result<Option<f64>, String> {
+  unsafe {
+    match &crate::IMAGE_BUFFER_PREVIEW {
+      Some(image_buffer) => Ok(convergence.update(launches, &image_buffer.lock().expect("mutex is poisoned"))),
+      None => Err("preview framebuffer is not initialized".into())
+    }
+  }
+}
+
+/// Rendering time, pauses excluded
+fn active(t0: Instant, paused_for: Duration, paused_at: Option<Instant>) -> Duration {
+  t0.elapsed() - paused_for - paused_at.map_or(Duration::default(), |x| x.elapsed())
+}
+
+fn capture_preview(timelapse: &mut Timelapse, iteration: u32) -> Result<(), String> {
+  unsafe {
+    match &crate::IMAGE_BUFFER_PREVIEW {
+      Some(image_buffer) => {
+        let image_buffer = image_buffer.lock().expect("mutex is poisoned");
+        timelapse.capture(iteration, image_buffer.dimensions(), &image_buffer)
+      },
+      None => Err("preview framebuffer is not initialized".into())
+    }
+  }
+}
+
+fn work_items(dimensions: &[u32]) -> u64 {
+  dimensions.iter().map(|&x| x as u64).product()
+}
+
+/// Statistics of `status`, cheap enough for every `GetState`
+fn refresh_stats(kernel_wrapper: &KernelWrapper, state: &mut ThreadState) {
+  state.device_memory = kernel_wrapper.device_memory();
+  match kernel_wrapper.read_counters() {
+    Ok((hits, frequency_max)) => {
+      state.hits = hits;
+      state.frequency_max = frequency_max;
+    },
+    Err(e) => println!("{} unable to read hit count: {}", TColor::BrightRed.paint("opencl::thr::err:"), e)
+  }
+}
+
+/// Per-launch kernel randoms, a pure function of the seed and launch index,
+/// so that a render continues identically from any `randgen_offset`
+fn launch_random(rng: &mut ChaCha8Rng, launch: u32) -> (u64, u64) {
+  rng.set_word_pos(launch as u128 * 4);
+  (rng.next_u64(), rng.next_u64())
+}
+
+fn snapshot(kernel_wrapper: &KernelWrapper, state: &ThreadState, randgen_offset: u32) -> Result<RenderState, String> {
+  let (accumulator, frequency_max) = kernel_wrapper.read_accumulator().map_err(|e| e.to_string())?;
+  Ok(RenderState { frequency_max, accumulator, ..snapshot_header(kernel_wrapper, state, randgen_offset) })
+}
+
+/// Everything but the accumulator
+fn snapshot_header(kernel_wrapper: &KernelWrapper, state: &ThreadState, randgen_offset: u32) -> RenderState {
+  RenderState {
+    image_size: kernel_wrapper.image_size,
+    frequency_max: 0,
+    randgen_offset,
+    seed: state.seed,
+    source_hash: kernel_wrapper.source_hash,
+    params: state.params.clone(),
+    accumulator: vec![]
+  }
+}
+
+/// Hands a checkpoint to the writer once its accumulator copy is done, without blocking
+fn poll_checkpoint(pending: &mut Option<(RenderState, Duration, PendingRead)>, writer: &CheckpointWriter) {
+  match pending.as_ref().map(|(_, _, read)| read.is_complete()) {
+    Some(Ok(true)) => (),
+    Some(Ok(false)) | None => return,
+    Some(Err(e)) => {
+      println!("{} unable to read checkpoint: {}", TColor::BrightRed.paint("opencl::thr::err:"), e);
+      *pending = None;
+      return;
+    }
+  }
+  let (header, elapsed, read) = pending.take().unwrap();
+  match read.wait() {
+    Ok((accumulator, frequency_max)) => {
+      writer.try_write(RenderState { frequency_max, accumulator, ..header }, elapsed);
+    },
+    Err(e) => println!("{} unable to read checkpoint: {}", TColor::BrightRed.paint("opencl::thr::err:"), e)
+  }
+}
+
+/// `KernelWrapper::resize`, the preview shows a restored image again, a lost one is reported and starts over
+fn resize(
+  kernel_wrapper: &mut KernelWrapper,
+  state: &mut ThreadState,
+  image_size: (u32, u32),
+  params: &RenderParams
+) -> Result<(), String> {
+  let error = match kernel_wrapper.resize(image_size, params) {
+    Ok(()) => return Ok(()),
+    Err(error) => error
+  };
+  match error {
+    ResizeError { message, lost: true, .. } => {
+      state.image_size = kernel_wrapper.image_size;
+      state.randgen_offset = 0;
+      state.samples = 0;
+      state.noise = None;
+      state.preview_render_interval = 1;
+      redraw_ui();
+      Err(format!("{}, the image was lost, now {}x{}", message, state.image_size.0, state.image_size.1))
+    },
+    ResizeError { message, restored: true, .. } => {
+      // rebuilt for the new parameters, with blank framebuffers
+      kernel_wrapper.set_params(&state.params)
+        .and_then(|_| kernel_wrapper.draw_image_preview())
+        .map_err(|e| format!("{}, unable to redraw the restored image: {}", message, e))?;
+      redraw_ui();
+      Err(message)
+    },
+    ResizeError { message, .. } => Err(message)
+  }
+}
+
+fn load_state(kernel_wrapper: &mut KernelWrapper, state: &mut ThreadState, path: &Path) -> Result<(), String> {
+  let render_state = RenderState::load(path).map_err(|e| e.to_string())?;
+  apply_state(kernel_wrapper, state, render_state)
+}
+
+fn apply_state(kernel_wrapper: &mut KernelWrapper, state: &mut ThreadState, render_state: RenderState) -> Result<(), String> {
+  render_state.params.validate()?;
+  if render_state.source_hash != kernel_wrapper.source_hash {
+    println!(
+      "{} kernel source differs from the saved state, further rendering will mix formulas",
+      TColor::Yellow.paint("opencl::thr::warn:")
+    );
+  }
+  if render_state.image_size != kernel_wrapper.image_size {
+    resize(kernel_wrapper, state, render_state.image_size, &render_state.params)?;
+  } else {
+    kernel_wrapper.set_params(&render_state.params).map_err(|e| e.to_string())?;
+  }
+  kernel_wrapper
+    .write_accumulator(&render_state.accumulator, render_state.frequency_max)
+    .map_err(|e| e.to_string())?;
+
+  state.image_size = kernel_wrapper.image_size;
+  state.randgen_offset = render_state.randgen_offset;
+  state.seed = render_state.seed;
+  state.params = render_state.params;
+  state.samples = state.randgen_offset as u64 * work_items(&state.dimensions);
+  state.noise = None;
+  state.preview_render_interval = 1;
+  Ok(())
+}
+
+/// Restores seed, parameters and worker dimensions of a saved image into a new render,
+/// returns the iteration count of the saved image
+fn load_params(
+  kernel_wrapper: &mut KernelWrapper,
+  state: &mut ThreadState,
+  path: &Path,
+  image_size: Option<(u32, u32)>
+) -> Result<u32, String> {
+  let metadata = Metadata::load(path)?;
+  metadata.params.validate()?;
+  if metadata.source_hash != kernel_wrapper.source_hash {
+    println!(
+      "{} kernel source differs from the saved image, see its \"Formula\" text chunk",
+      TColor::Yellow.paint("opencl::thr::warn:")
+    );
+  }
+  resize(kernel_wrapper, state, image_size.unwrap_or(metadata.image_size), &metadata.params)?;
+
+  state.image_size = kernel_wrapper.image_size;
+  state.randgen_offset = 0;
+  state.seed = metadata.seed;
+  state.dimensions = metadata.dimensions;
+  state.params = metadata.params;
+  state.samples = 0;
+  state.noise = None;
+  state.preview_render_interval = 1;
+  Ok(metadata.iterations)
+}
+
+fn set_params(kernel_wrapper: &KernelWrapper, state: &mut ThreadState, params: RenderParams) -> ActionResult {
+  match kernel_wrapper.set_params(&params) {
+    Ok(()) => {
+      state.params = params;
+      state.noise = None;
+      kernel_wrapper.draw_image_preview().unwrap();
+      redraw_ui();
+      ActionResult::Ok
+    },
+    Err(e) => {
+      println!("{}", e);
+      ActionResult::Err
+    }
+  }
+}
+
 fn redraw_ui(){
   unsafe {
     if let Some(ui_event) = &mut crate::TX3 {
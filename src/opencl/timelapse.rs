@@ -0,0 +1,163 @@
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  sync::mpsc::{sync_channel, SyncSender},
+  thread::{self, JoinHandle}
+};
+use super::{AnimatedFormat, AnimatedWriter, FileFormat, Pixels, write_image};
+
+/// `render --timelapse-every`
+pub const DEFAULT_TIMELAPSE_EVERY: u32 = 1;
+/// `render --timelapse-delay`, ms
+pub const DEFAULT_TIMELAPSE_DELAY: u32 = 100;
+
+/// `render --timelapse`
+#[derive(Clone, PartialEq, Debug)]
+pub struct TimelapseOptions {
+  /// directory of numbered frames, or a .gif / .apng animation
+  pub path: PathBuf,
+  /// every nth preview frame is captured
+  pub every: u32,
+  /// per frame of an animation, ms
+  pub delay: u32,
+  /// replace an existing animation
+  pub force: bool
+}
+
+enum Target {
+  Dir(PathBuf),
+  Animation(AnimatedWriter)
+}
+
+impl Target {
+  fn write(&mut self, iteration: u32, size: (u32, u32), pixels: &[u8]) -> Result<(), String> {
+    match self {
+      Target::Dir(dir) => {
+        let path = dir.join(format!("preview_{:010}.png", iteration));
+        write_image(&path, size, Pixels::Rgba8(pixels), FileFormat::Png, 0, None)
+          .map_err(|e| format!("\"{}\": {}", path.display(), e))
+      },
+      Target::Animation(writer) => writer.add_frame(pixels)
+    }
+  }
+
+  fn finish(self) -> Result<(), String> {
+    match self {
+      Target::Dir(_) => Ok(()),
+      Target::Animation(writer) => writer.finish()
+    }
+  }
+}
+
+/// (iteration, size, RGBA8 pixels)
+type Frame = (u32, (u32, u32), Vec<u8>);
+
+/// Preview frames drawn during a render, previews become sparser as the render goes on,
+/// see `preview_render_interval`. Frames are encoded on a separate thread, like checkpoints
+pub struct Timelapse {
+  sender: Option<SyncSender<Frame>>,
+  /// ends with the first error
+  thread: Option<JoinHandle<Result<(), String>>>,
+  every: u32,
+  /// previews left before the next capture
+  skip: u32,
+  /// captured frames
+  pub frames: u32
+}
+
+fn animated_format(path: &Path) -> Option<AnimatedFormat> {
+  match path.extension()?.to_str()?.to_lowercase().as_str() {
+    "gif" => Some(AnimatedFormat::Gif),
+    "apng" => Some(AnimatedFormat::Apng),
+    _ => None
+  }
+}
+
+impl Timelapse {
+  pub fn new(options: &TimelapseOptions, size: (u32, u32)) -> Result<Timelapse, String> {
+    let mut target = match animated_format(&options.path) {
+      Some(format) => Target::Animation(AnimatedWriter::new(&options.path, format, size, options.delay, options.force)?),
+      None => {
+        fs::create_dir_all(&options.path).map_err(|e| e.to_string())?;
+        Target::Dir(options.path.clone())
+      }
+    };
+    // a few frames of slack, the render only waits if the encoder falls further behind
+    let (sender, receiver) = sync_channel::<Frame>(4);
+    let thread = thread::spawn(move || {
+      for (iteration, size, pixels) in receiver {
+        target.write(iteration, size, &pixels)?;
+      }
+      target.finish()
+    });
+    Ok(Timelapse { sender: Some(sender), thread: Some(thread), every: options.every.max(1), skip: 0, frames: 0 })
+  }
+
+  /// Called after each preview, `iteration` is the `randgen_offset` the preview shows
+  pub fn capture(&mut self, iteration: u32, size: (u32, u32), pixels: &[u8]) -> Result<(), String> {
+    if self.skip > 0 {
+      self.skip -= 1;
+      return Ok(());
+    }
+    self.skip = self.every - 1;
+    let sent = match &self.sender {
+      Some(sender) => sender.send((iteration, size, pixels.to_vec())).is_ok(),
+      None => false
+    };
+    if !sent {
+      // the writer stopped, its error explains why
+      self.sender = None;
+      return Err(self.join().err().unwrap_or_else(|| "timelapse writer stopped".into()));
+    }
+    self.frames += 1;
+    Ok(())
+  }
+
+  fn join(&mut self) -> Result<(), String> {
+    match self.thread.take() {
+      Some(thread) => thread.join().unwrap_or_else(|_| Err("timelapse writer panicked".into())),
+      None => Ok(())
+    }
+  }
+
+  /// Blocks until every frame is encoded and the animation is written
+  pub fn finish(mut self) -> Result<(), String> {
+    self.sender = None;
+    self.join()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lib::TempDir;
+
+  #[test]
+  fn frames() {
+    let dir = TempDir::new("timelapse");
+    let options = TimelapseOptions { path: dir.to_path_buf(), every: 2, delay: 100, force: false };
+    let mut timelapse = Timelapse::new(&options, (2, 2)).unwrap();
+    for iteration in 0..5 {
+      timelapse.capture(iteration, (2, 2), &[0xFF; 16]).unwrap();
+    }
+    assert_eq!(timelapse.frames, 3);
+    timelapse.finish().unwrap();
+    let mut names = fs::read_dir(&dir).unwrap()
+      .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+      .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["preview_0000000000.png", "preview_0000000002.png", "preview_0000000004.png"]);
+  }
+
+  #[test]
+  fn writer_error() {
+    let dir = TempDir::new("timelapse_gif");
+    let path = dir.join("a.gif");
+    let options = TimelapseOptions { path: path.clone(), every: 1, delay: 100, force: true };
+    let mut timelapse = Timelapse::new(&options, (2, 2)).unwrap();
+    // wrong frame size, the writer stops and the next capture reports why
+    timelapse.capture(0, (1, 1), &[0xFF; 4]).ok();
+    let error = (1..10).map(|i| timelapse.capture(i, (1, 1), &[0xFF; 4])).find_map(Result::err);
+    assert!(error.unwrap().starts_with("frame size differs"));
+  }
+}
\ No newline at end of file
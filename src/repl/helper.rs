@@ -0,0 +1,380 @@
+use std::borrow::Cow;
+use rustyline::{Context, Helper};
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::hint::Hinter;
+use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
+use crate::{batch, opencl::{self, RenderParams}};
+use crate::scene::{Scene, SETTINGS};
+use super::tokenize::tokenize;
+
+/// Positional arguments completed as paths, `<command> [<subcommand>]`
+const PATH_COMMANDS: &[&str] = &[
+  "resume_checkpoint", "source", "save_state", "load_state", "merge", "load_params", "scene load", "scene save"
+];
+/// Options completed as paths
+const PATH_OPTIONS: &[&str] = &["output", "out", "checkpoint-dir", "timelapse", "gif", "apng"];
+/// Positional arguments completed with setting names
+const SETTING_COMMANDS: &[&str] = &["set", "get"];
+/// Options completed with parameter names
+const PARAM_OPTIONS: &[&str] = &["param"];
+
+struct OptionSpec {
+  long: &'static str,
+  short: Option<char>,
+  takes_value: bool,
+  possible_values: &'static [&'static str]
+}
+
+const fn flag(long: &'static str) -> OptionSpec {
+  OptionSpec { long, short: None, takes_value: false, possible_values: &[] }
+}
+
+const fn opt(long: &'static str) -> OptionSpec {
+  OptionSpec { long, short: None, takes_value: true, possible_values: &[] }
+}
+
+impl OptionSpec {
+  const fn short(self, short: char) -> OptionSpec {
+    OptionSpec { short: Some(short), ..self }
+  }
+
+  const fn values(self, possible_values: &'static [&'static str]) -> OptionSpec {
+    OptionSpec { possible_values, ..self }
+  }
+}
+
+struct CommandSpec {
+  /// "keyframe add" for nested subcommands
+  name: &'static str,
+  subcommands: &'static [&'static str],
+  options: &'static [OptionSpec]
+}
+
+const fn command(name: &'static str, options: &'static [OptionSpec]) -> CommandSpec {
+  CommandSpec { name, subcommands: &[], options }
+}
+
+/// Commands and options of `app`, `batch::sweep_app` and `batch::animate_app`, keep in sync
+const COMMANDS: &[CommandSpec] = &[
+  command("new", &[opt("dimensions").short('d'), opt("seed").short('s')]),
+  command("render", &[
+    opt("iter").short('i'), opt("dimensions").short('d'), opt("seed").short('s'),
+    opt("checkpoint-every"), opt("checkpoint-dir"), opt("checkpoint-keep"),
+    opt("timelapse"), opt("timelapse-every"), opt("timelapse-delay"),
+    opt("time"), opt("samples"), opt("until-noise"), flag("force")
+  ]),
+  command("pause", &[]),
+  command("resume", &[]),
+  command("resume_checkpoint", &[]),
+  command("status", &[]),
+  command("jobs", &[]),
+  command("cancel", &[]),
+  command("reorder", &[]),
+  command("density_estimation", &[opt("min"), opt("max"), opt("curve")]),
+  command("tone", &[opt("exposure"), opt("shift"), opt("gamma")]),
+  command("view", &[opt("center"), opt("zoom")]),
+  command("set", &[]),
+  command("get", &[]),
+  command("show", &[]),
+  command("save_state", &[]),
+  command("load_state", &[]),
+  command("merge", &[opt("output").short('o'), flag("force")]),
+  command("load_params", &[opt("dimensions").short('d')]),
+  CommandSpec { name: "scene", subcommands: &["load", "save"], options: &[] },
+  command("scene load", &[]),
+  command("scene save", &[]),
+  CommandSpec { name: "keyframe", subcommands: &["add", "remove", "list", "clear"], options: &[] },
+  command("keyframe add", &[opt("interpolation").values(&["linear", "smoothstep", "spline"])]),
+  command("keyframe remove", &[]),
+  command("keyframe list", &[]),
+  command("keyframe clear", &[]),
+  command("recompile", &[]),
+  command("save_image", &[
+    opt("alpha").values(&["opaque", "straight", "premultiplied"]),
+    opt("color"),
+    opt("depth").values(&["8", "16"]),
+    opt("output").short('o'),
+    opt("format").values(&["png", "jpg", "tiff", "bmp", "webp"]),
+    opt("quality"), opt("template"), flag("force")
+  ]),
+  command("source", &[]),
+  command("wait", &[]),
+  command("help", &[]),
+  command("exit", &[]),
+  command("sweep", &[
+    opt("param").short('p'), opt("iter").short('i'), opt("dimensions").short('d'), opt("size"),
+    opt("seed").short('s'), opt("out").short('o'), opt("thumbnail"), flag("force")
+  ]),
+  command("animate", &[
+    opt("frames").short('f'), opt("iter-per-frame").short('i'), opt("dimensions").short('d'), opt("size"),
+    opt("seed").short('s'), flag("fixed-seed"), opt("out").short('o'), opt("gif"), opt("apng"),
+    opt("delay"), flag("force")
+  ])
+];
+
+/// Completion, hints and bracket matching of the repl
+pub struct ReplHelper {
+  /// (command, long option, default value), shown as a hint after the option
+  defaults: Vec<(&'static str, &'static str, String)>,
+  filename: FilenameCompleter,
+  brackets: MatchingBracketHighlighter
+}
+
+fn candidates<'a, I: IntoIterator<Item = &'a str>>(values: I, word: &str, suffix: &str) -> Vec<Pair> {
+  values.into_iter()
+    .filter(|x| x.starts_with(word))
+    .map(|x| Pair { display: x.into(), replacement: format!("{}{}", x, suffix) })
+    .collect()
+}
+
+impl ReplHelper {
+  pub fn new(session: &Scene) -> ReplHelper {
+    let mut helper = ReplHelper {
+      defaults: vec![],
+      filename: FilenameCompleter::new(),
+      brackets: MatchingBracketHighlighter::new()
+    };
+    helper.set_defaults(session);
+    helper
+  }
+
+  /// Defaults of `new`, `render` and `save_image` follow the loaded scene
+  pub fn set_defaults(&mut self, session: &Scene) {
+    let join = |values: &[u32]| values.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ");
+    let size = format!("{} {}", session.image.width, session.image.height);
+    let seed = session.image.seed.map_or("random".to_string(), |x| x.to_string());
+    self.defaults = vec![
+      ("new", "dimensions", size.clone()),
+      ("new", "seed", seed.clone()),
+      ("render", "iter", session.render.iter.to_string()),
+      ("render", "dimensions", join(&session.render.dimensions)),
+      ("render", "seed", "current".into()),
+      ("render", "checkpoint-dir", opencl::DEFAULT_CHECKPOINT_DIR.into()),
+      ("render", "checkpoint-keep", opencl::DEFAULT_CHECKPOINT_KEEP.to_string()),
+      ("render", "timelapse-every", opencl::DEFAULT_TIMELAPSE_EVERY.to_string()),
+      ("render", "timelapse-delay", opencl::DEFAULT_TIMELAPSE_DELAY.to_string()),
+      ("render", "until-noise", match session.render.until_noise {
+        x if x > 0.0 => x.to_string(),
+        _ => "off".into()
+      }),
+      ("save_image", "alpha", session.output.alpha.name().into()),
+      ("save_image", "color", session.output.color.clone()),
+      ("save_image", "depth", session.output.depth.bits().to_string()),
+      ("save_image", "format", session.output.format.map_or("extension", |x| x.extension()).into()),
+      ("save_image", "quality", session.output.quality.to_string()),
+      ("save_image", "template", session.output.template.clone()),
+      ("sweep", "iter", session.render.iter.to_string()),
+      ("sweep", "dimensions", join(&session.render.dimensions)),
+      ("sweep", "size", size.clone()),
+      ("sweep", "seed", seed.clone()),
+      ("sweep", "thumbnail", batch::DEFAULT_THUMBNAIL.to_string()),
+      ("animate", "iter-per-frame", session.render.iter.to_string()),
+      ("animate", "dimensions", join(&session.render.dimensions)),
+      ("animate", "size", size),
+      ("animate", "seed", seed),
+      ("animate", "delay", batch::DEFAULT_DELAY.to_string())
+    ];
+  }
+
+  fn default_value(&self, command: &str, long: &str) -> Option<&str> {
+    self.defaults.iter()
+      .find(|(c, l, _)| *c == command && *l == long)
+      .map(|(_, _, value)| value.as_str())
+  }
+
+  /// Innermost command named by the leading words, and the number of words it spans
+  fn command(&self, words: &[&str]) -> Option<(&'static CommandSpec, usize)> {
+    let mut found = None;
+    for count in 1..=words.len() {
+      let name = words[..count].join(" ");
+      match COMMANDS.iter().find(|x| x.name == name) {
+        Some(command) => found = Some((command, count)),
+        None => break
+      }
+    }
+    found
+  }
+
+  fn option(&self, command: &'static CommandSpec, word: &str) -> Option<&'static OptionSpec> {
+    if word.starts_with("--") {
+      command.options.iter().find(|x| x.long == &word[2..])
+    } else if word.starts_with('-') && word.chars().count() == 2 {
+      command.options.iter().find(|x| x.short == word.chars().nth(1))
+    } else {
+      None
+    }
+  }
+}
+
+/// Words as the repl splits them, with quotes and escapes removed and variables left as they are
+fn preceding_words(line: &str) -> Vec<String> {
+  tokenize(line, |name| Some(format!("${{{}}}", name)))
+    .unwrap_or_else(|_| line.split_whitespace().map(String::from).collect())
+}
+
+impl Completer for ReplHelper {
+  type Candidate = Pair;
+
+  fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+    let head = &line[..pos];
+    let start = head.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let word = &head[start..];
+    let words = preceding_words(&head[..start]);
+    let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let (command, depth) = match self.command(&words) {
+      Some(found) => found,
+      None if words.is_empty() => {
+        let names = COMMANDS.iter().filter(|x| !x.name.contains(' ')).map(|x| x.name);
+        return Ok((start, candidates(names, word, " ")));
+      },
+      None => return Ok((start, vec![]))
+    };
+
+    // value of the preceding option
+    if let Some(option) = words.last().and_then(|x| self.option(command, x)).filter(|x| x.takes_value) {
+      if PATH_OPTIONS.contains(&option.long) {
+        return self.filename.complete(line, pos, ctx);
+      }
+      if PARAM_OPTIONS.contains(&option.long) {
+        return Ok((start, candidates(RenderParams::NAMES.iter().cloned(), word, "=")));
+      }
+      return Ok((start, candidates(option.possible_values.iter().cloned(), word, " ")));
+    }
+
+    if word.starts_with('-') {
+      let longs = command.options.iter()
+        .map(|x| format!("--{}", x.long))
+        .filter(|x| !words.contains(&x.as_str()))
+        .collect::<Vec<_>>();
+      return Ok((start, candidates(longs.iter().map(|x| x.as_str()), word, " ")));
+    }
+    if depth == words.len() && !command.subcommands.is_empty() {
+      return Ok((start, candidates(command.subcommands.iter().cloned(), word, " ")));
+    }
+    if PATH_COMMANDS.contains(&command.name) {
+      return self.filename.complete(line, pos, ctx);
+    }
+    if SETTING_COMMANDS.contains(&command.name) && depth == words.len() {
+      return Ok((start, candidates(SETTINGS.iter().map(|x| x.name), word, " ")));
+    }
+    if command.name == "show" && depth == words.len() {
+      let mut groups = SETTINGS.iter().map(|x| x.group).collect::<Vec<_>>();
+      groups.dedup();
+      return Ok((start, candidates(groups, word, " ")));
+    }
+    Ok((start, vec![]))
+  }
+}
+
+impl Hinter for ReplHelper {
+  /// Rest of a unique command or option, followed by its default value, e.g. `render --it` hints `er [64]`
+  fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+    if pos < line.len() {
+      return None;
+    }
+    let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let word = &line[start..];
+    let words = preceding_words(&line[..start]);
+    let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+    if word.is_empty() {
+      return None;
+    }
+
+    let command = match self.command(&words) {
+      Some((command, _)) => command,
+      None if words.is_empty() => {
+        let mut names = COMMANDS.iter().filter(|x| !x.name.contains(' ') && x.name.starts_with(word));
+        return match (names.next(), names.next()) {
+          (Some(command), None) => Some(command.name[word.len()..].to_string()),
+          _ => None
+        };
+      },
+      None => return None
+    };
+
+    // setting name, followed by its range
+    if SETTING_COMMANDS.contains(&command.name) && words.len() == 1 {
+      let mut settings = SETTINGS.iter().filter(|x| x.name.starts_with(word));
+      return match (settings.next(), settings.next()) {
+        (Some(setting), None) => match setting.describe_range() {
+          range if range.is_empty() => Some(setting.name[word.len()..].to_string()),
+          range => Some(format!("{} [{}]", &setting.name[word.len()..], range))
+        },
+        _ => None
+      };
+    }
+    if !word.starts_with("--") {
+      return None;
+    }
+    let mut options = command.options.iter().filter(|x| x.long.starts_with(&word[2..]));
+    let option = match (options.next(), options.next()) {
+      (Some(option), None) => option,
+      _ => return None
+    };
+    let rest = &option.long[word.len() - 2..];
+    let root = command.name.split(' ').next().unwrap_or_default();
+    match self.default_value(root, option.long) {
+      Some(value) => Some(format!("{} [{}]", rest, value)),
+      None if !rest.is_empty() => Some(rest.to_string()),
+      None => None
+    }
+  }
+}
+
+impl Highlighter for ReplHelper {
+  fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+    self.brackets.highlight(line, pos)
+  }
+
+  fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+    // dim
+    Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+  }
+
+  fn highlight_char(&self, line: &str, pos: usize) -> bool {
+    self.brackets.highlight_char(line, pos)
+  }
+}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+  use clap::ErrorKind;
+  use super::*;
+
+  /// Every completed command and option is accepted by the parser
+  #[test]
+  fn commands() {
+    let app = super::super::app();
+    for command in COMMANDS {
+      let name = command.name.split(' ').map(String::from).collect::<Vec<_>>();
+      let mut cases = vec![name.clone()];
+      for option in command.options {
+        let values = match option.possible_values {
+          [] => &["1"][..],
+          values => values
+        };
+        for value in values {
+          let mut args = name.clone();
+          args.push(format!("--{}", option.long));
+          if option.takes_value {
+            args.push(value.to_string());
+          }
+          cases.push(args);
+          if let Some(short) = option.short {
+            cases.push(name.iter().cloned().chain(vec![format!("-{}", short), value.to_string()]).collect());
+          }
+        }
+      }
+      for args in cases {
+        let result = app.clone().get_matches_from_safe(std::iter::once("repl".to_string()).chain(args.clone()));
+        if let Err(e) = result {
+          let rejected = [ErrorKind::UnknownArgument, ErrorKind::UnrecognizedSubcommand, ErrorKind::InvalidValue];
+          assert!(!rejected.contains(&e.kind), "{}: {}", args.join(" "), e.message);
+        }
+      }
+    }
+  }
+}
\ No newline at end of file
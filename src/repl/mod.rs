@@ -1,114 +1,389 @@
+mod helper;
+mod tokenize;
+
 use std::sync::{Arc, Mutex, mpsc::Sender, mpsc::Receiver};
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use clap::App;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use term_painter::{ToStyle, Color};
 use crate::opencl;
+use crate::scene::{self, Scene};
+use crate::batch;
+use helper::ReplHelper;
+use tokenize::tokenize;
 
-pub fn init(
+/// Command line options
+pub struct Options {
+  pub scene: Option<PathBuf>,
+  /// run instead of the interactive session, see `Repl::run_script`
+  pub script: Option<PathBuf>,
+  /// commands from stdin, after the script if any
+  pub stdin: bool
+}
+
+struct Repl {
   tx1_: Arc<Mutex<Sender<opencl::Action>>>,
-  rx2_: Arc<Mutex<Receiver<opencl::ActionResult>>>
-) {
-  // `()` can be used when no completer is required
-  let mut rustyline = Editor::<()>::new();
+  rx2_: Arc<Mutex<Receiver<opencl::ActionResult>>>,
+  /// defaults of `new`, `render` and `save_image`, shared with the ui
+  session: Arc<Mutex<Scene>>,
+  app: App<'static, 'static>,
+  /// canonical paths of the running `source` scripts, innermost last
+  scripts: Vec<PathBuf>
+}
 
-  let mut matches = clap_app!(repl =>
+fn app() -> App<'static, 'static> {
+  clap_app!(repl =>
       (@subcommand new =>
         (@arg dimensions: -d --dimensions +takes_value +multiple)
+        (@arg seed: -s --seed +takes_value)
       )
       (@subcommand render =>
         (@arg iter: -i --iter +takes_value)
         (@arg dimensions: -d --dimensions +takes_value +multiple)
+        (@arg seed: -s --seed +takes_value)
+        (@arg checkpoint_every: --("checkpoint-every") +takes_value)
+        (@arg checkpoint_dir: --("checkpoint-dir") +takes_value)
+        (@arg checkpoint_keep: --("checkpoint-keep") +takes_value)
+        (@arg timelapse: --timelapse +takes_value)
+        (@arg timelapse_every: --("timelapse-every") +takes_value)
+        (@arg timelapse_delay: --("timelapse-delay") +takes_value)
+        (@arg time: --time +takes_value)
+        (@arg samples: --samples +takes_value)
+        (@arg until_noise: --("until-noise") +takes_value)
+        (@arg force: --force)
+      )
+      (@subcommand pause => )
+      (@subcommand resume =>
+        (alias: "continue")
+      )
+      (@subcommand resume_checkpoint =>
+        (@arg dir: +required)
+      )
+      (@subcommand status =>
+        (alias: "stats")
+      )
+      (@subcommand jobs => )
+      (@subcommand cancel =>
+        (@arg id: +required)
+      )
+      (@subcommand reorder =>
+        (@arg id: +required)
+        (@arg position: +required)
+      )
+      (@subcommand density_estimation =>
+        (@arg toggle: possible_value[on off])
+        (@arg min: --min +takes_value)
+        (@arg max: --max +takes_value)
+        (@arg curve: --curve +takes_value)
+      )
+      (@subcommand tone =>
+        (@arg exposure: --exposure +takes_value)
+        (@arg shift: --shift +takes_value +allow_hyphen_values)
+        (@arg gamma: --gamma +takes_value)
+      )
+      (@subcommand view =>
+        (@arg center: --center +takes_value +allow_hyphen_values number_of_values(2))
+        (@arg zoom: --zoom +takes_value)
+      )
+      (@subcommand set =>
+        (@arg name: +required)
+        (@arg value: +required +multiple +allow_hyphen_values)
+      )
+      (@subcommand get =>
+        (@arg name: +required)
+      )
+      (@subcommand show =>
+        (@arg group:)
+      )
+      (@subcommand save_state =>
+        (@arg file: +required)
+      )
+      (@subcommand load_state =>
+        (@arg file: +required)
+      )
+      (@subcommand merge =>
+        (@arg files: +required +multiple)
+        (@arg output: -o --output +takes_value)
+        (@arg force: --force)
+      )
+      (@subcommand load_params =>
+        (@arg file: +required)
+        (@arg dimensions: -d --dimensions +takes_value +multiple)
+      )
+      (@subcommand scene =>
+        (@subcommand load =>
+          (@arg file: +required)
+        )
+        (@subcommand save =>
+          (@arg file: +required)
+        )
+      )
+      (@subcommand keyframe =>
+        (@subcommand add =>
+          (@arg time: +required)
+          (@arg interpolation: --interpolation +takes_value possible_value[linear smoothstep spline])
+        )
+        (@subcommand remove =>
+          (@arg time: +required)
+        )
+        (@subcommand list => )
+        (@subcommand clear => )
       )
       (@subcommand recompile => )
-      (@subcommand save_image => )
+      (@subcommand save_image =>
+        (@arg alpha: --alpha +takes_value possible_value[opaque straight premultiplied])
+        (@arg color: --color +takes_value)
+        (@arg depth: --depth +takes_value possible_values(&["8", "16"]))
+        (@arg output: -o --output +takes_value)
+        (@arg format: --format +takes_value possible_value[png jpg tiff bmp webp])
+        (@arg quality: --quality +takes_value)
+        (@arg template: --template +takes_value)
+        (@arg force: --force)
+      )
+      (@subcommand source =>
+        (@arg file: +required)
+      )
+      (@subcommand wait => )
       (@subcommand help => )
       (@subcommand exit => )
-  ).help(
+  ).subcommand(batch::sweep_app()).subcommand(batch::animate_app()).help(
 r#"USAGE (repl interface): <command> <opts>
+  'single quotes' are literal, "double quotes" expand variables, \ escapes a character,
+  $width $height $iter $seed and parameter names (e.g. $zoom) expand to current values,
+  other $names to environment variables, # starts a comment unless it starts a #rrggbb color
 
 Commmands:
 new         new image, clear if existing
   -d, --dimensions=[width height | 512 512] image dimensions
+  -s, --seed=[u64 | random]                 random seed
+  (defaults are taken from the loaded scene, if any. Sizes beyond the device
+   memory are rejected with the largest size that fits, the current image is kept)
 
 render      render kernel
   -i, --iter=[value | 64]                   iteration count
   -d, --dimensions=[values... | 512 512 1]  worker dimensions
+  -s, --seed=[u64 | current]                reseed, same seed and parameters
+                                            give bit-identical renders
+  --checkpoint-every=[iterations]           save the state periodically
+  --checkpoint-dir=[dir | checkpoints]
+  --checkpoint-keep=[count | 3]             older checkpoints are deleted
+  --timelapse=[dir | file.gif | file.apng]  capture the previews, dir/preview_<iteration>.png
+  --timelapse-every=[n | 1]                 every nth preview, previews get sparser as the render goes
+  --timelapse-delay=[ms | 100]              frame delay of a gif or apng timelapse
+  --force                                   overwrite an existing gif or apng timelapse
+  --time=[duration]                         stop after e.g. 90s, 30m or 2h, pauses excluded
+  --samples=[count]                         stop after e.g. 1e10 sampled points,
+                                            one per work item and iteration
+  --until-noise=[value]                     stop once the relative noise of the preview,
+                                            estimated from successive previews, is below;
+                                            targets below the 8-bit rounding of the
+                                            preview are reported and not reached
+  (the first limit reached ends the render and is reported, --iter is unbounded
+   unless given. `set until_noise <value>` applies --until-noise to every render,
+   the noise estimate is shown on the progress bar and by `status`)
+
+pause         suspend the running render, parameters still update the preview
+resume        continue a paused render (alias: continue)
+resume_checkpoint <dir>  continue a checkpointed render from its newest valid
+                         checkpoint, time limits count the rendering time
+                         before the checkpoint
+
+status      print render status: job, progress, elapsed time and eta,
+            samples per second, samples since new, hit ratio (samples
+            landing in the image), noise estimate, frequency_max and
+            device memory
+  (alias: stats, answered while rendering)
+
+jobs        list the running render and the commands queued during it
+cancel <id>             cancel a queued job or the running render
+reorder <id> <position> move a queued job, position 1 runs next
+  (commands other than status, pause, resume, the parameter commands, jobs, cancel and reorder
+   are queued while rendering, so are changes of the view and formula parameters,
+   ctrl-c stops the render and clears the queue)
+
+density_estimation  adaptive blur of low-density regions (preview and output)
+  [on|off]                                  enable or disable the filter
+  --min=[value | 0]                         minimum radius, px
+  --max=[value | 9]                         maximum radius, px
+  --curve=[value | 0.4]                     radius falloff with sample count
+
+tone        tone mapping, pow(exposure * density, 1 / gamma) + shift
+  --exposure=[value | 1]
+  --shift=[value | 0]
+  --gamma=[value | 1]
+
+view        screen window over the projection
+  --center=[x y | -0.5 0]                   point drawn in the middle of the image
+  --zoom=[value | 1]
+  (density_estimation, tone and view change the settings of the same names,
+   with the ranges listed by `show`; the ui has a control for each of them)
+
+set <name> <value>  change a setting: a default of `new`, `render` and `save_image`,
+                    or a kernel parameter, applied immediately or, for view and
+                    formula parameters during a render, after it
+get <name>          print a setting
+show [group]        list settings with their values, ranges and descriptions,
+                    groups: image render output formula tone view density_estimation preview
+  (settings are saved with `scene save`)
+
+save_state <file>  save accumulator, seed and parameters to continue the render later
+load_state <file>  restore a saved state, `render` continues where it left off
+merge <files...>   sum saved states of the same scene rendered with different seeds,
+                   e.g. on several machines, and continue with the result
+  -o, --output=[file]                       also save the result, as an image if the
+                                            extension is one, otherwise as a state
+  --force                                   overwrite an existing image
+load_params <png>  new image with the parameters and seed embedded in a saved png
+  -d, --dimensions=[width height | saved]   image dimensions, e.g. to re-render larger
+
+scene load <file>  apply a scene file (toml, json): parameters, new image,
+                   and the defaults of `render` and `save_image`
+scene save <file>  save the current configuration as a scene, toml unless *.json
+
+  defaults of the repl and the ui buttons come from
+  $XDG_CONFIG_HOME/opencl_attractor/config.toml (~/.config if unset), a scene
+  file applied on startup; values missing from loaded scenes are taken from it.
+  History is kept in $XDG_STATE_HOME/opencl_attractor/history (~/.local/state)
+
+keyframe add <time>     store the current parameters as a keyframe of `animate`,
+                        replacing the one at the same time
+  --interpolation=[linear|smoothstep|spline | smoothstep]  easing towards the next keyframe
+keyframe remove <time>
+keyframe list
+keyframe clear          (keyframes are saved with `scene save`)
 
 recompile   compile kernel and redraw preview
-save_image  save image in current directory
+save_image  save image, in current directory by default
+  -o, --output=[file | dir]                 file or directory, may contain placeholders
+  --alpha=[opaque|straight|premultiplied | opaque]  density as alpha channel
+  --color=[[#]rrggbb | ffffff]              foreground color of transparent images
+  --depth=[8|16 | 8]                        bits per channel, 16 with png and tiff only
+  --format=[png|jpg|tiff|bmp | extension]   file format, png if there is no extension
+                                            (webp is not supported by the image backend)
+  --quality=[1..100 | 90]                   jpg quality
+  --template=[name | opencl_attractor-{timestamp}.{ext}]
+                                            file name, placeholders: {formula} {width}
+                                            {height} {iter} {seed} {depth} {timestamp} {ext}
+  --force                                   overwrite existing files
+sweep       render every combination of parameter values into a directory,
+            with a labelled contact_sheet.png
+  -p, --param=[name=start..end:steps]       repeatable, e.g. -p a=0.5..2.0:16 -p b=-1..1:8
+                                            names: a b c d (formula, see `show formula`), exposure shift gamma,
+                                            center_x center_y zoom, de_min de_max de_curve
+  -o, --out=[dir]                           output directory
+  -i, --iter=[value | 64]                   iterations per image
+  -d, --dimensions=[values... | 512 512]    worker dimensions
+  --size=[width height | 512 512]           image size
+  -s, --seed=[u64 | random]                 seed shared by every image
+  --thumbnail=[px | 192]                    contact sheet cell width
+  --force                                   overwrite existing images
+  (also available headless: opencl_attractor sweep ...)
+
+animate     render the keyframes into an image sequence, frame_00000.png..., or an animation
+  -f, --frames=[count]                      frames spanning the first to the last keyframe
+  -o, --out=[dir]                           output directory of the frames
+  --gif=[file]                              animated gif, 256 colors per frame
+  --apng=[file]                             animated png, lossless
+  --delay=[ms | 40]                         frame delay of the gif and apng
+  -i, --iter-per-frame=[value | 64]         iterations per frame
+  -d, --dimensions=[values... | 512 512]    worker dimensions
+  --size=[width height | 512 512]           image size
+  -s, --seed=[u64 | random]                 seed of frame 0, frame n uses seed + n
+  --fixed-seed                              same seed on every frame
+  --force                                   overwrite existing frames and animations
+  (also available headless: opencl_attractor --scene <file> animate ...)
+
+source <file>  run the commands of a script, one per line, `#` starts a comment;
+               the script stops at the first failing command
+               (also available on startup: opencl_attractor --script <file>, or --stdin)
+wait        block until the render and the commands queued during it are done,
+            fails if any of them failed or the render is paused;
+            lines of a script wait for the previous one
+
 help        print help message
 exit        terminate application
-"#);
+"#)
+}
+
+pub fn init(
+  tx1_: Arc<Mutex<Sender<opencl::Action>>>,
+  rx2_: Arc<Mutex<Receiver<opencl::ActionResult>>>,
+  options: Options
+) {
+  let defaults = scene::defaults();
+  let mut repl = Repl { tx1_, rx2_, session: Arc::new(Mutex::new(defaults.clone())), app: app(), scripts: vec![] };
+  unsafe {
+    crate::SESSION = Some(repl.session.clone());
+  }
+  {
+    let tx1 = repl.tx1_.lock().unwrap();
+    let rx2 = repl.rx2_.lock().unwrap();
+    match options.scene {
+      Some(path) => match load_scene(&tx1, &rx2, &path) {
+        Ok(scene) => *repl.session.lock().unwrap() = scene,
+        Err(e) => println!("{} {}: {}", Color::BrightRed.paint("repl::err:"), path.display(), e)
+      },
+      None if defaults != Scene::default() => {
+        if let Err(e) = apply_scene(&tx1, &rx2, &defaults) {
+          println!("{} config: {}", Color::BrightRed.paint("repl::err:"), e);
+        }
+      },
+      None => ()
+    }
+  }
+
+  // non-interactive, stops at the first failing command
+  if options.script.is_some() || options.stdin {
+    let mut ok = true;
+    if let Some(path) = options.script {
+      ok = repl.source(&path);
+    }
+    if ok && options.stdin {
+      let stdin = std::io::stdin();
+      ok = repl.run_script(stdin.lock(), "stdin");
+    }
+    ok = ok && repl.execute("wait");
+    std::process::exit(if ok { 0 } else { 1 });
+  }
+
+  let mut rustyline = Editor::<ReplHelper>::new();
+  rustyline.set_helper(Some(ReplHelper::new(&repl.session.lock().unwrap())));
+  let history = scene::history_path();
+  if let Some(path) = &history {
+    match rustyline.load_history(path) {
+      // missing on first run
+      Err(ReadlineError::Io(ref e)) if e.kind() == std::io::ErrorKind::NotFound => (),
+      Err(e) => println!("{} unable to load history, \"{}\": {}", Color::BrightRed.paint("repl::err:"), path.display(), e),
+      Ok(()) => ()
+    }
+  }
 
   'repl: loop {
+    // hints show the defaults of the loaded scene
+    if let Some(helper) = rustyline.helper_mut() {
+      helper.set_defaults(&repl.session.lock().unwrap());
+    }
     let readline = rustyline.readline("> ");
     match readline {
       Ok(line) => {
-        if let Ok(command) = matches
-          .clone()
-          .get_matches_from_safe(
-            format!("repl {}", line).trim().split(" ")
-          ){
+        if !line.trim().is_empty() {
           rustyline.add_history_entry(line.as_str());
-
-          let tx1 = tx1_.lock().unwrap();
-          let rx2 = rx2_.lock().unwrap();
-
-          match command.subcommand() {
-
-            /*** new ***/
-            ("new", Some(command)) => {
-              let dimensions = values_t!(command, "dimensions", u32).unwrap_or(vec![512, 512]);
-              if dimensions.len() != 2{
-                println!("{} {}", Color::BrightRed.paint("repl::err:"), "invalid syntax");
-                continue 'repl;
-              }
-              tx1.send(opencl::Action::New(dimensions[0], dimensions[1])).unwrap();
-              rx2.recv().unwrap();
-            },
-
-            /*** render ***/
-            ("render", Some(command)) => {
-              let iter = value_t!(command, "iter", u32).unwrap_or(64);
-              let dimensions = values_t!(command, "dimensions", u32).unwrap_or(vec![512, 512]);
-              tx1.send(opencl::Action::Render(iter, dimensions, None)).unwrap();
-              rx2.recv().unwrap();
-            },
-
-            /*** recompile ***/
-            ("recompile", Some(_)) => {
-              tx1.send(opencl::Action::Recompile).unwrap();
-              rx2.recv().unwrap();
-            },
-
-            /*** save_image ***/
-            ("save_image", Some(_)) => {
-              tx1.send(opencl::Action::SaveImage).unwrap();
-              rx2.recv().unwrap();
-            },
-
-            /*** help ***/
-            ("help", Some(_)) => {
-              matches.print_long_help().ok();
-            },
-
-            /*** exit ***/
-            ("exit", Some(_)) => {
-              if opencl::thread_interrupt(tx1_.clone(), rx2_.clone()){
-                std::process::exit(0);
-              }
-            },
-
-            _ => println!("{} {}", Color::BrightRed.paint("repl::err:"), "unknown command")
+          // saved right away, `exit` and closing the ui end the process
+          if let Some(path) = &history {
+            if let Err(e) = save_history(&rustyline, path) {
+              println!("{} unable to save history, \"{}\": {}", Color::BrightRed.paint("repl::err:"), path.display(), e);
+            }
           }
-        } else {
-          println!("{} {}", Color::BrightRed.paint("repl::err:"), "invalid syntax");
         }
+        repl.execute(&line);
       },
 
       // CTRL-C
       Err(ReadlineError::Interrupted) => {
-        if opencl::thread_interrupt(tx1_.clone(), rx2_.clone()){
+        if opencl::thread_interrupt(repl.tx1_.clone(), repl.rx2_.clone()){
           std::process::exit(0);
         }
       },
@@ -121,4 +396,827 @@ exit        terminate application
       }
     }
   }
+}
+
+impl Repl {
+  /// Runs the commands of a script file, scripts may source others but not themselves
+  fn source(&mut self, path: &Path) -> bool {
+    let opened = path.canonicalize().and_then(|canonical| File::open(&canonical).map(|file| (canonical, file)));
+    let (canonical, file) = match opened {
+      Ok((canonical, _)) if self.scripts.contains(&canonical) => {
+        println!("{} \"{}\" is already running", Color::BrightRed.paint("repl::err:"), path.display());
+        return false;
+      },
+      Ok(opened) => opened,
+      Err(e) => {
+        println!("{} \"{}\": {}", Color::BrightRed.paint("repl::err:"), path.display(), e);
+        return false;
+      }
+    };
+    self.scripts.push(canonical);
+    let ok = self.run_script(BufReader::new(file), &path.display().to_string());
+    self.scripts.pop();
+    ok
+  }
+
+  /// One command per line, see `tokenize`. Each command waits for the previous one,
+  /// renders included. Stops at the first failing command, like `set -e`
+  fn run_script<R: BufRead>(&mut self, reader: R, name: &str) -> bool {
+    for (number, line) in reader.lines().enumerate() {
+      let line = match line {
+        Ok(line) => line,
+        Err(e) => {
+          println!("{} {}: {}", Color::BrightRed.paint("repl::err:"), name, e);
+          return false;
+        }
+      };
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      println!("{} {}", Color::BrightBlack.paint(format!("{}:{}>", name, number + 1)), line);
+      if !self.execute(line) || !self.execute("wait") {
+        println!("{} {}:{}: command failed, script stopped", Color::BrightRed.paint("repl::err:"), name, number + 1);
+        return false;
+      }
+    }
+    true
+  }
+
+  fn state(&self) -> Option<opencl::ThreadState> {
+    let tx1 = self.tx1_.lock().unwrap();
+    let rx2 = self.rx2_.lock().unwrap();
+    tx1.send(opencl::Action::GetState).unwrap();
+    match rx2.recv().unwrap() {
+      opencl::ActionResult::State(state) => Some(state),
+      _ => None
+    }
+  }
+
+  /// Runs one command, false if it failed
+  fn execute(&mut self, line: &str) -> bool {
+    let state = if line.contains('$') { self.state() } else { None };
+    let words = match tokenize(line, |name| variable(state.as_ref(), name)) {
+      Ok(words) => words,
+      Err(e) => {
+        println!("{} {}", Color::BrightRed.paint("repl::err:"), e);
+        return false;
+      }
+    };
+    if words.is_empty() {
+      return true;
+    }
+    let command = match self.app.clone().get_matches_from_safe(std::iter::once("repl".to_string()).chain(words)) {
+      Ok(command) => command,
+      Err(_) => {
+        println!("{} {}", Color::BrightRed.paint("repl::err:"), "invalid syntax");
+        return false;
+      }
+    };
+
+    // commands that lock the channels themselves
+    match command.subcommand() {
+
+      /*** source ***/
+      ("source", Some(command)) => {
+        return self.source(Path::new(command.value_of("file").unwrap()));
+      },
+
+      /*** exit ***/
+      ("exit", Some(_)) => {
+        if opencl::thread_interrupt(self.tx1_.clone(), self.rx2_.clone()){
+          std::process::exit(0);
+        }
+        return true;
+      },
+
+      _ => ()
+    }
+
+    let tx1_ = self.tx1_.clone();
+    let rx2_ = self.rx2_.clone();
+    let tx1 = tx1_.lock().unwrap();
+    let rx2 = rx2_.lock().unwrap();
+    let session_ = self.session.clone();
+    let mut session = session_.lock().unwrap();
+
+    match command.subcommand() {
+
+      /*** new ***/
+      ("new", Some(command)) => {
+        let (image_size, seed) = match (image_size(command), batch::optional_value(command, "seed")) {
+          (Ok(image_size), Ok(seed)) => (
+            image_size.unwrap_or((session.image.width, session.image.height)),
+            seed.or(session.image.seed)
+          ),
+          (Err(e), _) | (_, Err(e)) => {
+            println!("{} {}", Color::BrightRed.paint("repl::err:"), e);
+            return false;
+          }
+        };
+        tx1.send(opencl::Action::New(image_size.0, image_size.1, seed)).unwrap();
+        rx2.recv().unwrap().is_ok()
+      },
+
+      /*** render ***/
+      ("render", Some(command)) => {
+        match render_action(command, &session) {
+          Ok(action) => {
+            tx1.send(action).unwrap();
+            rx2.recv().unwrap().is_ok()
+          },
+          Err(e) => {
+            println!("{} {}", Color::BrightRed.paint("repl::err:"), e);
+            false
+          }
+        }
+      },
+
+      /*** resume ***/
+      ("resume", Some(_)) => {
+        tx1.send(opencl::Action::Resume).unwrap();
+        rx2.recv().unwrap().is_ok()
+      },
+
+      /*** resume_checkpoint ***/
+      ("resume_checkpoint", Some(command)) => {
+        report(resume_checkpoint(&tx1, &rx2, Path::new(command.value_of("dir").unwrap())))
+      },
+
+      /*** pause ***/
+      ("pause", Some(_)) => {
+        tx1.send(opencl::Action::Pause).unwrap();
+        rx2.recv().unwrap().is_ok()
+      },
+
+      /*** status ***/
+      ("status", Some(_)) => {
+        tx1.send(opencl::Action::GetState).unwrap();
+        if let opencl::ActionResult::State(state) = rx2.recv().unwrap() {
+          print_status(&state);
+        }
+        true
+      },
+
+      /*** jobs ***/
+      ("jobs", Some(_)) => {
+        tx1.send(opencl::Action::Jobs).unwrap();
+        if let opencl::ActionResult::Jobs(jobs) = rx2.recv().unwrap() {
+          if jobs.is_empty() {
+            println!("no jobs");
+          }
+          for job in jobs {
+            match job.progress {
+              Some((done, total)) => println!(
+                "#{:<4} {} [{}/{}, {:.0}%]",
+                job.id, job.description, done, total, done as f64 * 100.0 / total.max(1) as f64
+              ),
+              None => println!("#{:<4} {} [queued]", job.id, job.description)
+            }
+          }
+        }
+        true
+      },
+
+      /*** cancel ***/
+      ("cancel", Some(command)) => {
+        match value_t!(command, "id", u32) {
+          Ok(id) => {
+            tx1.send(opencl::Action::Cancel(id)).unwrap();
+            rx2.recv().unwrap().is_ok()
+          },
+          Err(_) => {
+            println!("{} {}", Color::BrightRed.paint("repl::err:"), "invalid syntax");
+            false
+          }
+        }
+      },
+
+      /*** reorder ***/
+      ("reorder", Some(command)) => {
+        match (value_t!(command, "id", u32), value_t!(command, "position", usize)) {
+          (Ok(id), Ok(position)) if position > 0 => {
+            tx1.send(opencl::Action::Reorder(id, position - 1)).unwrap();
+            rx2.recv().unwrap().is_ok()
+          },
+          _ => {
+            println!("{} {}", Color::BrightRed.paint("repl::err:"), "invalid syntax");
+            false
+          }
+        }
+      },
+
+      /*** density_estimation ***/
+      ("density_estimation", Some(command)) => param_command(&tx1, &rx2, &mut session, "density_estimation", command, &[
+        ("toggle", &["de_enabled"]), ("min", &["de_min"]), ("max", &["de_max"]), ("curve", &["de_curve"])
+      ]),
+
+      /*** tone ***/
+      ("tone", Some(command)) => param_command(&tx1, &rx2, &mut session, "tone", command, &[
+        ("exposure", &["exposure"]), ("shift", &["shift"]), ("gamma", &["gamma"])
+      ]),
+
+      /*** view ***/
+      ("view", Some(command)) => param_command(&tx1, &rx2, &mut session, "view", command, &[
+        ("center", &["center_x", "center_y"]), ("zoom", &["zoom"])
+      ]),
+
+      /*** set ***/
+      ("set", Some(command)) => {
+        let name = command.value_of("name").unwrap();
+        let value = command.values_of("value").unwrap().collect::<Vec<_>>().join(" ");
+        let result = set_settings(&tx1, &rx2, &mut session, &[(name, value.as_str())])
+          .and_then(|_| session.get(name))
+          .map(|value| println!("{} {} = {}", Color::Green.paint("repl:"), name, value));
+        report(result)
+      },
+
+      /*** get ***/
+      ("get", Some(command)) => {
+        let name = command.value_of("name").unwrap();
+        let result = sync_params(&tx1, &rx2, &mut session)
+          .and_then(|_| session.get(name))
+          .map(|value| println!("{}", value));
+        report(result)
+      },
+
+      /*** show ***/
+      ("show", Some(command)) => {
+        let group = command.value_of("group");
+        if let Some(group) = group {
+          if !scene::SETTINGS.iter().any(|x| x.group == group) {
+            println!("{} unknown group \"{}\"", Color::BrightRed.paint("repl::err:"), group);
+            return false;
+          }
+        }
+        if let Err(e) = sync_params(&tx1, &rx2, &mut session) {
+          println!("{} {}", Color::BrightRed.paint("repl::err:"), e);
+          return false;
+        }
+        let mut current = "";
+        for setting in scene::SETTINGS.iter().filter(|x| group.is_none_or(|group| x.group == group)) {
+          if setting.group != current {
+            current = setting.group;
+            println!("{}", Color::BrightWhite.paint(format!("[{}]", current)));
+          }
+          println!(
+            "  {:<22} {:<16} {:<30} {}",
+            setting.name, session.get(setting.name).unwrap_or_default(), setting.describe_range(), setting.description
+          );
+        }
+        true
+      },
+
+      /*** save_state ***/
+      ("save_state", Some(command)) => {
+        let file = command.value_of("file").unwrap();
+        tx1.send(opencl::Action::SaveState(file.into())).unwrap();
+        rx2.recv().unwrap().is_ok()
+      },
+
+      /*** load_state ***/
+      ("load_state", Some(command)) => {
+        let file = command.value_of("file").unwrap();
+        tx1.send(opencl::Action::LoadState(file.into())).unwrap();
+        rx2.recv().unwrap().is_ok()
+      },
+
+      /*** merge ***/
+      ("merge", Some(command)) => {
+        let files = command.values_of("files").unwrap().map(PathBuf::from).collect();
+        tx1.send(opencl::Action::Merge(files)).unwrap();
+        if !rx2.recv().unwrap().is_ok() {
+          return false;
+        }
+        if let Some(output) = command.value_of("output") {
+          let output = PathBuf::from(output);
+          if opencl::is_image_path(&output) {
+            tx1.send(opencl::Action::SaveImage(opencl::SaveOptions {
+              output: Some(output),
+              format: None,
+              force: command.is_present("force"),
+              ..session.save_options().unwrap_or_default()
+            })).unwrap();
+          } else {
+            tx1.send(opencl::Action::SaveState(output)).unwrap();
+          }
+          return rx2.recv().unwrap().is_ok();
+        }
+        true
+      },
+
+      /*** load_params ***/
+      ("load_params", Some(command)) => {
+        let file = command.value_of("file").unwrap();
+        let image_size = match image_size(command) {
+          Ok(image_size) => image_size,
+          Err(e) => {
+            println!("{} {}", Color::BrightRed.paint("repl::err:"), e);
+            return false;
+          }
+        };
+        tx1.send(opencl::Action::LoadParams(Path::new(file).into(), image_size)).unwrap();
+        rx2.recv().unwrap().is_ok()
+      },
+
+      /*** scene ***/
+      ("scene", Some(command)) => {
+        let result = match command.subcommand() {
+          ("load", Some(command)) => {
+            load_scene(&tx1, &rx2, Path::new(command.value_of("file").unwrap()))
+              .map(|scene| *session = scene)
+          },
+          ("save", Some(command)) => {
+            save_scene(&tx1, &rx2, &session, Path::new(command.value_of("file").unwrap()))
+          },
+          _ => Err("invalid syntax".into())
+        };
+        report(result)
+      },
+
+      /*** recompile ***/
+      ("recompile", Some(_)) => {
+        tx1.send(opencl::Action::Recompile).unwrap();
+        rx2.recv().unwrap().is_ok()
+      },
+
+      /*** save_image ***/
+      ("save_image", Some(command)) => {
+        let mut options = session.save_options().unwrap_or_default();
+        if let Some(alpha) = command.value_of("alpha") {
+          options.alpha = alpha.parse().unwrap();
+        }
+        if let Some(color) = command.value_of("color") {
+          match opencl::parse_color(color) {
+            Ok(color) => options.foreground = color,
+            Err(e) => {
+              println!("{} {}", Color::BrightRed.paint("repl::err:"), e);
+              return false;
+            }
+          }
+        }
+        if let Some(depth) = command.value_of("depth") {
+          options.depth = depth.parse().unwrap();
+        }
+        if let Some(format) = command.value_of("format") {
+          match format.parse() {
+            Ok(format) => options.format = Some(format),
+            Err(e) => {
+              println!("{} {}", Color::BrightRed.paint("repl::err:"), e);
+              return false;
+            }
+          }
+        }
+        if let Some(output) = command.value_of("output") {
+          options.output = Some(output.into());
+        }
+        match batch::optional_value(command, "quality") {
+          Ok(quality) => options.quality = quality.unwrap_or(options.quality),
+          Err(e) => {
+            println!("{} {}", Color::BrightRed.paint("repl::err:"), e);
+            return false;
+          }
+        }
+        if let Some(template) = command.value_of("template") {
+          options.template = template.into();
+        }
+        options.force = command.is_present("force");
+        tx1.send(opencl::Action::SaveImage(options)).unwrap();
+        rx2.recv().unwrap().is_ok()
+      },
+
+      /*** sweep ***/
+      ("sweep", Some(command)) => {
+        let result = batch::Sweep::from_matches(command, &session)
+          .and_then(|sweep| sweep.run(&tx1, &rx2, &session.save_options().unwrap_or_default()));
+        report(result)
+      },
+
+      /*** keyframe ***/
+      ("keyframe", Some(command)) => {
+        let result = match command.subcommand() {
+          ("add", Some(command)) => value_t!(command, "time", f32)
+            .map_err(|e| e.to_string())
+            .and_then(|time| {
+              let interpolation = command.value_of("interpolation").unwrap_or("smoothstep").parse()?;
+              let params = batch::current_params(&tx1, &rx2)?;
+              session.keyframes.retain(|k| k.time != time);
+              let index = session.keyframes.iter().position(|k| k.time > time).unwrap_or(session.keyframes.len());
+              session.keyframes.insert(index, scene::Keyframe { time, interpolation, params });
+              Ok(())
+            }),
+          ("remove", Some(command)) => value_t!(command, "time", f32)
+            .map_err(|e| e.to_string())
+            .map(|time| session.keyframes.retain(|k| k.time != time)),
+          ("list", Some(_)) => {
+            for k in &session.keyframes {
+              let view = &k.params.view;
+              let formula = &k.params.formula;
+              println!(
+                "{:>8} {:?}: center {} {}, zoom {}, exposure {}, gamma {}, a b c d {} {} {} {}",
+                k.time, k.interpolation, view.center[0], view.center[1], view.zoom,
+                k.params.tone.exposure, k.params.tone.gamma, formula.a, formula.b, formula.c, formula.d
+              );
+            }
+            Ok(())
+          },
+          ("clear", Some(_)) => {
+            session.keyframes.clear();
+            Ok(())
+          },
+          _ => Err("invalid syntax".into())
+        };
+        report(result)
+      },
+
+      /*** animate ***/
+      ("animate", Some(command)) => {
+        let result = batch::Animation::from_matches(command, &session)
+          .and_then(|animation| animation.run(
+            &tx1, &rx2, &session.keyframes, &session.save_options().unwrap_or_default()
+          ));
+        report(result)
+      },
+
+      /*** help ***/
+      ("help", Some(_)) => {
+        self.app.print_long_help().ok();
+        true
+      },
+
+      /*** wait ***/
+      ("wait", Some(_)) => {
+        wait(&tx1, &rx2)
+      },
+
+      _ => {
+        println!("{} {}", Color::BrightRed.paint("repl::err:"), "unknown command");
+        false
+      }
+    }
+  }
+}
+
+/// `$width`, `$height`, `$iter`, `$seed` and parameter names of the current image,
+/// then environment variables
+fn variable(state: Option<&opencl::ThreadState>, name: &str) -> Option<String> {
+  let value = state.and_then(|state| match name {
+    "width" => Some(state.image_size.0.to_string()),
+    "height" => Some(state.image_size.1.to_string()),
+    "iter" => Some(state.randgen_offset.to_string()),
+    "seed" => Some(state.seed.to_string()),
+    name => state.params.get(name).ok().map(|x| x.to_string())
+  });
+  value.or_else(|| std::env::var(name).ok())
+}
+
+/// Blocks until the running render and the jobs queued during it are done,
+/// false if any of them failed since the last `wait`, or if the render is paused
+fn wait(tx1: &Sender<opencl::Action>, rx2: &Receiver<opencl::ActionResult>) -> bool {
+  loop {
+    tx1.send(opencl::Action::GetState).unwrap();
+    if let opencl::ActionResult::State(state) = rx2.recv().unwrap() {
+      if state.paused {
+        println!("{} the render is paused, `resume` it first", Color::BrightRed.paint("repl::err:"));
+        return false;
+      }
+    }
+    tx1.send(opencl::Action::Jobs).unwrap();
+    match rx2.recv().unwrap() {
+      opencl::ActionResult::Jobs(ref jobs) if !jobs.is_empty() => (),
+      _ => break
+    }
+    std::thread::sleep(std::time::Duration::from_millis(100));
+  }
+  tx1.send(opencl::Action::Failures).unwrap();
+  match rx2.recv().unwrap() {
+    opencl::ActionResult::Failures(failures) => {
+      for job in &failures {
+        match job.progress {
+          Some((done, total)) => println!(
+            "{} job #{} {} stopped at {}/{}", Color::BrightRed.paint("repl::err:"), job.id, job.description, done, total
+          ),
+          None => println!("{} job #{} {} failed", Color::BrightRed.paint("repl::err:"), job.id, job.description)
+        }
+      }
+      failures.is_empty()
+    },
+    _ => false
+  }
+}
+
+/// `-d width height`, None if absent
+fn image_size(command: &clap::ArgMatches) -> Result<Option<(u32, u32)>, String> {
+  match batch::optional_values::<u32>(command, "dimensions")? {
+    Some(dimensions) if dimensions.len() == 2 => {
+      let image_size = (dimensions[0], dimensions[1]);
+      opencl::check_image_size(image_size)?;
+      Ok(Some(image_size))
+    },
+    Some(_) => Err("image dimensions are width and height".into()),
+    None => Ok(None)
+  }
+}
+
+/// Options omitted from `render` are taken from the session
+fn render_action(command: &clap::ArgMatches, session: &Scene) -> Result<opencl::Action, String> {
+  let mut limits = render_limits(command)?;
+  let iter = match batch::optional_value(command, "iter")? {
+    Some(iter) => iter,
+    None if !limits.is_empty() => opencl::UNBOUNDED,
+    None => session.render.iter
+  };
+  if limits.noise.is_none() && session.render.until_noise > 0.0 {
+    limits.noise = Some(session.render.until_noise);
+  }
+  let dimensions = batch::optional_values(command, "dimensions")?.unwrap_or_else(|| session.render.dimensions.clone());
+  let seed = batch::optional_value(command, "seed")?;
+  let checkpoint = match batch::optional_value(command, "checkpoint_every")? {
+    Some(0) => return Err("checkpoint interval must be positive".into()),
+    Some(every) => Some(opencl::CheckpointOptions {
+      dir: command.value_of("checkpoint_dir").unwrap_or(opencl::DEFAULT_CHECKPOINT_DIR).into(),
+      every,
+      keep: batch::optional_value(command, "checkpoint_keep")?.unwrap_or(opencl::DEFAULT_CHECKPOINT_KEEP)
+    }),
+    None => None
+  };
+  let timelapse = match command.value_of("timelapse") {
+    Some(path) => Some(opencl::TimelapseOptions {
+      path: path.into(),
+      every: batch::optional_value(command, "timelapse_every")?.unwrap_or(opencl::DEFAULT_TIMELAPSE_EVERY),
+      delay: batch::optional_value(command, "timelapse_delay")?.unwrap_or(opencl::DEFAULT_TIMELAPSE_DELAY),
+      force: command.is_present("force")
+    }),
+    None => None
+  };
+  Ok(opencl::Action::Render(iter, dimensions, seed, checkpoint, timelapse, limits, None))
+}
+
+fn render_limits(command: &clap::ArgMatches) -> Result<opencl::RenderLimits, String> {
+  Ok(opencl::RenderLimits {
+    time: match command.value_of("time") {
+      Some(value) => Some(opencl::parse_duration(value)?),
+      None => None
+    },
+    samples: match command.value_of("samples") {
+      Some(value) => Some(opencl::parse_samples(value)?),
+      None => None
+    },
+    noise: match command.value_of("until_noise") {
+      Some(value) => match value.parse::<f64>() {
+        Ok(x) if x > 0.0 && x.is_finite() => Some(x),
+        _ => return Err(format!("invalid noise target \"{}\"", value))
+      },
+      None => None
+    }
+  })
+}
+
+fn print_status(state: &opencl::ThreadState) {
+  match &state.progress {
+    Some(progress) => {
+      let (done, total) = progress.job.progress.unwrap_or((0, 0));
+      let seconds = progress.elapsed.as_secs_f64();
+      println!(
+        "rendering:     job #{} {}{}",
+        progress.job.id, progress.job.description, if state.paused { " (paused)" } else { "" }
+      );
+      let done = if progress.unbounded { format!("{} iterations", done) } else { format!("{}/{}", done, total) };
+      match progress.fraction() {
+        Some(fraction) => println!("progress:      {} ({:.1}%)", done, fraction * 100.0),
+        None => println!("progress:      {} (n/a)", done)
+      }
+      if !progress.limits.is_empty() {
+        println!("limits:        {}", progress.limits.describe());
+      }
+      println!("elapsed:       {}", format_duration(seconds));
+      match progress.fraction().filter(|&x| x > 0.0) {
+        Some(fraction) => println!("eta:           {}", format_duration(seconds * (1.0 - fraction) / fraction)),
+        None => println!("eta:           n/a")
+      }
+      if seconds > 0.0 {
+        println!("samples/s:     {}", format_count(progress.samples as f64 / seconds));
+      }
+    },
+    None => println!("rendering:     no")
+  }
+  println!("iterations:    {}", state.randgen_offset);
+  println!("samples:       {} since new", format_count(state.samples as f64));
+  println!("hit ratio:     {:.2}% in view", state.hits as f64 * 100.0 / state.samples.max(1) as f64);
+  match state.noise {
+    Some(noise) => println!("noise:         {:.4} relative, from successive previews", noise),
+    None => println!("noise:         unknown, estimated during a render")
+  }
+  println!("frequency_max: {}", state.frequency_max);
+  println!("device memory: {:.1} MiB", state.device_memory as f64 / (1024.0 * 1024.0));
+  println!("seed:          {}", state.seed);
+}
+
+/// hh:mm:ss
+fn format_duration(seconds: f64) -> String {
+  let seconds = seconds.round() as u64;
+  format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// e.g. 1.25G
+fn format_count(x: f64) -> String {
+  let units = ["", "k", "M", "G", "T", "P"];
+  let mut x = x;
+  let mut unit = 0;
+  while x >= 1000.0 && unit < units.len() - 1 {
+    x /= 1000.0;
+    unit += 1;
+  }
+  match unit {
+    0 => format!("{:.0}", x),
+    _ => format!("{:.2}{}", x, units[unit])
+  }
+}
+
+fn report(result: Result<(), String>) -> bool {
+  match result {
+    Ok(()) => true,
+    Err(e) => {
+      println!("{} {}", Color::BrightRed.paint("repl::err:"), e);
+      false
+    }
+  }
+}
+
+/// `tone`, `view` and `density_estimation`: each option sets the settings named after it,
+/// one per value, then the settings are printed
+fn param_command(
+  tx1: &Sender<opencl::Action>,
+  rx2: &Receiver<opencl::ActionResult>,
+  session: &mut Scene,
+  name: &str,
+  command: &clap::ArgMatches,
+  options: &[(&str, &[&str])]
+) -> bool {
+  let values = options.iter()
+    .filter_map(|(option, names)| command.values_of(option).map(|values| names.iter().cloned().zip(values)))
+    .flatten()
+    .collect::<Vec<_>>();
+  let result = set_settings(tx1, rx2, session, &values).map(|_| {
+    let settings = options.iter()
+      .flat_map(|(_, names)| names.iter())
+      .map(|&x| format!("{} {}", x, session.get(x).unwrap_or_default()))
+      .collect::<Vec<_>>();
+    println!("{}: {}", name, settings.join(", "));
+  });
+  report(result)
+}
+
+/// Kernel parameters of the session follow the opencl thread
+fn sync_params(
+  tx1: &Sender<opencl::Action>,
+  rx2: &Receiver<opencl::ActionResult>,
+  session: &mut Scene
+) -> Result<(), String> {
+  session.params = batch::current_params(tx1, rx2)?;
+  Ok(())
+}
+
+/// Sets several settings at once, type and range checked by the registry.
+/// Kernel parameters are applied by the opencl thread, during a render too
+pub fn set_settings(
+  tx1: &Sender<opencl::Action>,
+  rx2: &Receiver<opencl::ActionResult>,
+  session: &mut Scene,
+  values: &[(&str, &str)]
+) -> Result<(), String> {
+  sync_params(tx1, rx2, session)?;
+  let mut scene = session.clone();
+  let mut params = false;
+  for &(name, value) in values {
+    params |= scene::find_setting(name)?.is_param();
+    scene.set(name, value)?;
+  }
+  // constraints between values, e.g. de_min <= de_max
+  scene.validate()?;
+  if params {
+    tx1.send(opencl::Action::SetParams(scene.params.clone())).unwrap();
+    // view and formula changes are queued during a render, the session follows right away
+    if !rx2.recv().unwrap().is_accepted() {
+      return Err("unable to apply parameters".into());
+    }
+  }
+  *session = scene;
+  Ok(())
+}
+
+/// Loads the newest valid checkpoint and renders the remaining iterations, within the remaining limits
+fn resume_checkpoint(
+  tx1: &Sender<opencl::Action>,
+  rx2: &Receiver<opencl::ActionResult>,
+  dir: &Path
+) -> Result<(), String> {
+  let mut job = opencl::CheckpointJob::load(dir)?;
+  job.options.dir = dir.into();
+  let path = opencl::latest_checkpoint(dir)?;
+  // the remaining iterations depend on the loaded state, it must not be queued
+  tx1.send(opencl::Action::GetState).unwrap();
+  if let opencl::ActionResult::State(state) = rx2.recv().unwrap() {
+    if state.rendering {
+      return Err("a render is in progress, cancel it first".into());
+    }
+  }
+  tx1.send(opencl::Action::LoadState(path)).unwrap();
+  if rx2.recv().unwrap() != opencl::ActionResult::Ok {
+    return Err("unable to load checkpoint".into());
+  }
+  tx1.send(opencl::Action::GetState).unwrap();
+  let randgen_offset = match rx2.recv().unwrap() {
+    opencl::ActionResult::State(state) => state.randgen_offset,
+    _ => return Err("unable to get render state".into())
+  };
+  let limits = match job.remaining_limits() {
+    Some(limits) if randgen_offset < job.target => limits,
+    _ => {
+      println!("{} render is already complete", Color::Green.paint("repl:"));
+      return Ok(());
+    }
+  };
+  // renders bounded by limits only stay unbounded, the thread caps the count at the offset
+  let iterations = if job.target == opencl::UNBOUNDED { opencl::UNBOUNDED } else { job.target - randgen_offset };
+  tx1.send(opencl::Action::Render(iterations, job.dimensions, None, Some(job.options), None, limits, None)).unwrap();
+  if !rx2.recv().unwrap().is_ok() {
+    return Err("unable to resume the render".into());
+  }
+  Ok(())
+}
+
+/// Parameters and a new image from the scene, which becomes the session defaults
+fn load_scene(
+  tx1: &Sender<opencl::Action>,
+  rx2: &Receiver<opencl::ActionResult>,
+  path: &Path
+) -> Result<Scene, String> {
+  let scene = Scene::load_over(path, &scene::defaults())?;
+  match &scene.formula {
+    Some(formula) if *formula != opencl::load_formula() => println!(
+      "{} kernel/main.cl differs from the formula the scene was saved with, see its `formula`",
+      Color::Yellow.paint("repl::warn:")
+    ),
+    _ => ()
+  }
+  apply_scene(tx1, rx2, &scene)?;
+  println!("{} scene loaded from \"{}\"", Color::Green.paint("repl:"), path.display());
+  Ok(scene)
+}
+
+/// Parameters and a new image of the scene
+fn apply_scene(
+  tx1: &Sender<opencl::Action>,
+  rx2: &Receiver<opencl::ActionResult>,
+  scene: &Scene
+) -> Result<(), String> {
+  tx1.send(opencl::Action::SetParams(scene.params.clone())).unwrap();
+  if !rx2.recv().unwrap().is_ok() {
+    return Err("unable to apply parameters".into());
+  }
+  tx1.send(opencl::Action::New(scene.image.width, scene.image.height, scene.image.seed)).unwrap();
+  if !rx2.recv().unwrap().is_ok() {
+    return Err("unable to create image".into());
+  }
+  Ok(())
+}
+
+fn save_history(rustyline: &Editor<ReplHelper>, path: &Path) -> Result<(), String> {
+  if let Some(dir) = path.parent() {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+  }
+  rustyline.save_history(path).map_err(|e| e.to_string())
+}
+
+fn save_scene(
+  tx1: &Sender<opencl::Action>,
+  rx2: &Receiver<opencl::ActionResult>,
+  session: &Scene,
+  path: &Path
+) -> Result<(), String> {
+  tx1.send(opencl::Action::GetState).unwrap();
+  let state = match rx2.recv().unwrap() {
+    opencl::ActionResult::State(state) => state,
+    _ => return Err("unable to get render state".into())
+  };
+  let scene = Scene {
+    formula: Some(opencl::load_formula()),
+    image: scene::Image {
+      width: state.image_size.0,
+      height: state.image_size.1,
+      seed: Some(state.seed)
+    },
+    render: scene::Render {
+      iter: if state.randgen_offset > 0 { state.randgen_offset } else { session.render.iter },
+      dimensions: state.dimensions,
+      until_noise: session.render.until_noise
+    },
+    output: session.output.clone(),
+    params: state.params,
+    keyframes: session.keyframes.clone()
+  };
+  scene.save(path)?;
+  println!("{} scene saved to \"{}\"", Color::Green.paint("repl:"), path.display());
+  Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,148 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Shell-like word splitting of a command line:
+/// - `'...'` is taken literally
+/// - `"..."` expands variables, `\` escapes `"`, `\`, and `$`
+/// - `\` escapes any character outside of quotes
+/// - `$name` and `${name}` expand to `variable(name)`
+/// - `#` at the start of a word comments out the rest of the line, unless the word is a `#rrggbb` color
+pub fn tokenize<F>(line: &str, variable: F) -> Result<Vec<String>, String>
+    where F: Fn(&str) -> Option<String> {
+  let mut words = vec![];
+  let mut chars = line.chars().peekable();
+  loop {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+      chars.next();
+    }
+    match chars.peek().cloned() {
+      None => return Ok(words),
+      Some('#') if !is_color(chars.clone()) => return Ok(words),
+      _ => ()
+    }
+
+    let mut word = String::new();
+    while let Some(&c) = chars.peek() {
+      if c.is_whitespace() {
+        break;
+      }
+      chars.next();
+      match c {
+        '\'' => loop {
+          match chars.next() {
+            Some('\'') => break,
+            Some(c) => word.push(c),
+            None => return Err("unterminated '".into())
+          }
+        },
+        '"' => loop {
+          match chars.next() {
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+              Some(c @ '"') | Some(c @ '\\') | Some(c @ '$') => word.push(c),
+              Some(c) => {
+                word.push('\\');
+                word.push(c);
+              },
+              None => return Err("unterminated \"".into())
+            },
+            Some('$') => word.push_str(&expand(&mut chars, &variable)?),
+            Some(c) => word.push(c),
+            None => return Err("unterminated \"".into())
+          }
+        },
+        '\\' => match chars.next() {
+          Some(c) => word.push(c),
+          None => return Err("trailing \\".into())
+        },
+        '$' => word.push_str(&expand(&mut chars, &variable)?),
+        c => word.push(c)
+      }
+    }
+    words.push(word);
+  }
+}
+
+/// `#` followed by six hex digits and the end of the word
+fn is_color(chars: Peekable<Chars>) -> bool {
+  let word = chars.skip(1).take_while(|c| !c.is_whitespace()).collect::<Vec<_>>();
+  word.len() == 6 && word.iter().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Variable following a `$`, a lone `$` is kept
+fn expand<F>(chars: &mut Peekable<Chars>, variable: &F) -> Result<String, String>
+    where F: Fn(&str) -> Option<String> {
+  let is_name = |c: &char| c.is_ascii_alphanumeric() || *c == '_';
+  let mut name = String::new();
+  if chars.peek() == Some(&'{') {
+    chars.next();
+    loop {
+      match chars.next() {
+        Some('}') => break,
+        Some(c) => name.push(c),
+        None => return Err("unterminated ${".into())
+      }
+    }
+  } else {
+    while let Some(c) = chars.peek().cloned().filter(is_name) {
+      name.push(c);
+      chars.next();
+    }
+    if name.is_empty() {
+      return Ok("$".into());
+    }
+  }
+  variable(&name).ok_or_else(|| format!("unknown variable ${}", name))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn words(line: &str) -> Result<Vec<String>, String> {
+    tokenize(line, |name| match name {
+      "iter" => Some("64".into()),
+      "name" => Some("a b".into()),
+      _ => None
+    })
+  }
+
+  #[test]
+  fn quoting() {
+    assert_eq!(words("  save_image  -o out.png ").unwrap(), vec!["save_image", "-o", "out.png"]);
+    assert_eq!(words("a 'b c' \"d e\" f'g h'i").unwrap(), vec!["a", "b c", "d e", "fg hi"]);
+    assert_eq!(words("'$iter \\n' \"\"").unwrap(), vec!["$iter \\n", ""]);
+  }
+
+  #[test]
+  fn escapes() {
+    assert_eq!(words("a\\ b \\'c").unwrap(), vec!["a b", "'c"]);
+    assert_eq!(words("\"\\\" \\\\ \\$iter \\n\"").unwrap(), vec!["\" \\ $iter \\n"]);
+    assert!(words("a\\").is_err());
+  }
+
+  #[test]
+  fn variables() {
+    assert_eq!(words("-i $iter x${iter}y \"$name\" $name").unwrap(), vec!["-i", "64", "x64y", "a b", "a b"]);
+    assert_eq!(words("cost $ 5").unwrap(), vec!["cost", "$", "5"]);
+    assert_eq!(words("$unknown").unwrap_err(), "unknown variable $unknown");
+    assert!(words("${iter").is_err());
+  }
+
+  #[test]
+  fn unterminated() {
+    assert_eq!(words("'abc").unwrap_err(), "unterminated '");
+    assert_eq!(words("\"abc").unwrap_err(), "unterminated \"");
+    assert_eq!(words("\"abc\\").unwrap_err(), "unterminated \"");
+  }
+
+  #[test]
+  fn comments() {
+    assert!(words("# render -i 64").unwrap().is_empty());
+    assert_eq!(words("render -i 64 # long").unwrap(), vec!["render", "-i", "64"]);
+    assert_eq!(words("save_image --color #ff0000 # red").unwrap(), vec!["save_image", "--color", "#ff0000"]);
+    assert_eq!(words("color #ff00").unwrap(), vec!["color"]);
+    assert_eq!(words("color #ff0000ff").unwrap(), vec!["color"]);
+    assert_eq!(words("a#b '#c'").unwrap(), vec!["a#b", "#c"]);
+  }
+}
\ No newline at end of file
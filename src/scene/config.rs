@@ -0,0 +1,36 @@
+use std::{env, path::PathBuf};
+use term_painter::{ToStyle, Color as TColor};
+use super::Scene;
+
+const APP_DIR: &str = "opencl_attractor";
+
+/// `$<var>/opencl_attractor`, or `$HOME/<fallback>/opencl_attractor` if unset or relative
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+  let base = env::var_os(var)
+    .map(PathBuf::from)
+    .filter(|x| x.is_absolute())
+    .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))?;
+  Some(base.join(APP_DIR))
+}
+
+/// `$XDG_CONFIG_HOME/opencl_attractor/config.toml`
+pub fn config_path() -> Option<PathBuf> {
+  xdg_dir("XDG_CONFIG_HOME", ".config").map(|x| x.join("config.toml"))
+}
+
+/// `$XDG_STATE_HOME/opencl_attractor/history`, repl history
+pub fn history_path() -> Option<PathBuf> {
+  xdg_dir("XDG_STATE_HOME", ".local/state").map(|x| x.join("history"))
+}
+
+/// Built-in defaults, overridden by the config file, a scene in the same format as `scene save`.
+/// An invalid config file is reported and ignored
+pub fn defaults() -> Scene {
+  match config_path().filter(|x| x.is_file()) {
+    Some(path) => Scene::load(&path).unwrap_or_else(|e| {
+      println!("{} \"{}\": {}", TColor::Yellow.paint("config::warn:"), path.display(), e);
+      Scene::default()
+    }),
+    None => Scene::default()
+  }
+}
\ No newline at end of file
@@ -0,0 +1,223 @@
+mod timeline;
+mod registry;
+mod config;
+
+use std::{fs, path::{Path, PathBuf}};
+use serde::{Serialize, Deserialize};
+use crate::opencl::{RenderParams, SaveOptions, AlphaMode, BitDepth, FileFormat, parse_color};
+pub use timeline::*;
+pub use registry::*;
+pub use config::*;
+
+/// `new`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Image {
+  pub width: u32,
+  pub height: u32,
+  /// random if omitted
+  pub seed: Option<u64>
+}
+
+impl Default for Image {
+  fn default() -> Self {
+    Image {
+      width: 512,
+      height: 512,
+      seed: None
+    }
+  }
+}
+
+/// `render`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Render {
+  pub iter: u32,
+  pub dimensions: Vec<u32>,
+  /// noise estimate that ends a render, 0 is off
+  pub until_noise: f64
+}
+
+impl Default for Render {
+  fn default() -> Self {
+    Render {
+      iter: 64,
+      dimensions: vec![512, 512],
+      until_noise: 0.0
+    }
+  }
+}
+
+/// `save_image`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Output {
+  pub alpha: AlphaMode,
+  pub color: String,
+  pub depth: BitDepth,
+  /// taken from the file extension if omitted
+  pub format: Option<FileFormat>,
+  pub quality: u8,
+  /// current directory if omitted
+  pub dir: Option<PathBuf>,
+  pub template: String
+}
+
+impl Default for Output {
+  fn default() -> Self {
+    Output {
+      alpha: AlphaMode::Opaque,
+      color: "ffffff".into(),
+      depth: BitDepth::Eight,
+      format: None,
+      quality: 90,
+      dir: None,
+      template: SaveOptions::DEFAULT_TEMPLATE.into()
+    }
+  }
+}
+
+/// Complete description of a render, as TOML or JSON
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scene {
+  /// kernel/main.cl the scene was saved with, compared on load
+  pub formula: Option<String>,
+  pub image: Image,
+  pub render: Render,
+  pub output: Output,
+  pub params: RenderParams,
+  /// `animate`, sorted by time. TOML has no empty array of tables after the ones above
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub keyframes: Vec<Keyframe>
+}
+
+fn is_json(path: &Path) -> bool {
+  path.extension().is_some_and(|x| x == "json")
+}
+
+/// Values of `layer` replace those of `base`, tables are merged key by key
+fn merge_values(base: &mut serde_json::Value, layer: serde_json::Value) {
+  match (base, layer) {
+    (serde_json::Value::Object(base), serde_json::Value::Object(layer)) => {
+      for (key, value) in layer {
+        match base.get_mut(&key) {
+          Some(x) => merge_values(x, value),
+          None => {
+            base.insert(key, value);
+          }
+        }
+      }
+    },
+    (base, layer) => *base = layer
+  }
+}
+
+impl Scene {
+  pub fn load(path: &Path) -> Result<Scene, String> {
+    Scene::load_over(path, &Scene::default())
+  }
+
+  /// Values missing from the file are taken from `base`, e.g. the user `defaults`
+  pub fn load_over(path: &Path, base: &Scene) -> Result<Scene, String> {
+    let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let layer: serde_json::Value = if is_json(path) {
+      serde_json::from_str(&source).map_err(|e| e.to_string())?
+    } else {
+      toml::from_str(&source).map_err(|e| e.to_string())?
+    };
+    // formula and keyframes belong to a single scene
+    let base = Scene { formula: None, keyframes: vec![], ..base.clone() };
+    let mut value = serde_json::to_value(&base).map_err(|e| e.to_string())?;
+    merge_values(&mut value, layer);
+    let scene: Scene = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    scene.validate()?;
+    Ok(scene)
+  }
+
+  /// TOML unless the extension is .json
+  pub fn save(&self, path: &Path) -> Result<(), String> {
+    let source = if is_json(path) {
+      serde_json::to_string_pretty(self).map_err(|e| e.to_string())?
+    } else {
+      toml::to_string(self).map_err(|e| e.to_string())?
+    };
+    fs::write(path, source).map_err(|e| e.to_string())
+  }
+
+  /// Ranges of the settings registry, then the constraints between values
+  pub fn validate(&self) -> Result<(), String> {
+    for setting in SETTINGS {
+      setting.check(&self.get(setting.name)?).map_err(|e| format!("{}: {}", setting.group, e))?;
+    }
+    self.save_options().map_err(|e| format!("output: {}", e))?;
+    validate_keyframes(&self.keyframes).map_err(|e| format!("keyframes: {}", e))?;
+    self.params.validate().map_err(|e| format!("params: {}", e))
+  }
+
+  pub fn save_options(&self) -> Result<SaveOptions, String> {
+    Ok(SaveOptions {
+      alpha: self.output.alpha,
+      foreground: parse_color(&self.output.color)?,
+      depth: self.output.depth,
+      format: self.output.format,
+      quality: self.output.quality,
+      output: self.output.dir.clone(),
+      template: self.output.template.clone(),
+      force: false
+    })
+  }
+
+  /// Value of a registry setting, as `set` accepts it
+  pub fn get(&self, name: &str) -> Result<String, String> {
+    Ok(find_setting(name)?.get(self))
+  }
+
+  /// Checks the type and range of the registry, not the constraints between values
+  pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+    find_setting(name)?.set(self, value)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lib::TempDir;
+
+  fn scene() -> Scene {
+    Scene {
+      formula: Some("#define loop \\\n  z = c_powr(z, 2) + pixel;\n".into()),
+      output: Output {
+        alpha: AlphaMode::Premultiplied,
+        depth: BitDepth::Sixteen,
+        format: Some(FileFormat::Tiff),
+        ..Output::default()
+      },
+      keyframes: vec![Keyframe { time: 1.0, interpolation: Interpolation::Spline, params: RenderParams::default() }],
+      ..Scene::default()
+    }
+  }
+
+  #[test]
+  fn round_trip() {
+    let dir = TempDir::new("scene");
+    for name in &["scene.toml", "scene.json"] {
+      let path = dir.join(name);
+      scene().save(&path).unwrap();
+      assert_eq!(Scene::load(&path).unwrap(), scene());
+    }
+  }
+
+  #[test]
+  fn output_values() {
+    let source = toml::to_string(&scene()).unwrap();
+    assert!(source.contains("alpha = \"premultiplied\""));
+    assert!(source.contains("depth = 16"));
+    assert!(source.contains("format = \"tiff\""));
+    let output: Output = toml::from_str("alpha = \"straight\"\ndepth = 8\nformat = \"jpeg\"").unwrap();
+    assert_eq!((output.alpha, output.depth, output.format), (AlphaMode::Straight, BitDepth::Eight, Some(FileFormat::Jpg)));
+    assert!(toml::from_str::<Output>("depth = 12").is_err());
+    assert!(toml::from_str::<Output>("alpha = \"inverted\"").is_err());
+  }
+}
\ No newline at end of file
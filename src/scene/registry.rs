@@ -0,0 +1,329 @@
+use std::{path::PathBuf, str::FromStr};
+use crate::opencl::parse_color;
+use super::Scene;
+
+/// Value type of a setting, as accepted by `set`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+  Float,
+  Int,
+  /// an integer, or `random`
+  Seed,
+  /// on, off, true, false
+  Bool,
+  Choice(&'static [&'static str]),
+  Text,
+  /// whitespace separated integers, each within the range
+  IntList
+}
+
+/// Entry of the settings registry, see `Scene::get` and `Scene::set`
+#[derive(Clone, Copy, Debug)]
+pub struct Setting {
+  pub name: &'static str,
+  pub group: &'static str,
+  pub kind: Kind,
+  /// inclusive, numbers only
+  pub range: Option<(f64, f64)>,
+  pub unit: &'static str,
+  pub description: &'static str,
+  /// value as `set` accepts it
+  get: fn(&Scene) -> String,
+  /// value passed `check`
+  set: fn(&mut Scene, &str) -> Result<(), String>
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn setting(
+  name: &'static str,
+  group: &'static str,
+  kind: Kind,
+  range: Option<(f64, f64)>,
+  unit: &'static str,
+  description: &'static str,
+  get: fn(&Scene) -> String,
+  set: fn(&mut Scene, &str) -> Result<(), String>
+) -> Setting {
+  Setting { name, group, kind, range, unit, description, get, set }
+}
+
+/// factor of a step of positive values, e.g. exposure and zoom
+const STEP_FACTOR: f64 = 1.25;
+/// other values, view coordinates are divided by the zoom
+const STEP: f64 = 0.05;
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+  value.parse().map_err(|_| format!("invalid value \"{}\"", value))
+}
+
+fn join(values: &[u32]) -> String {
+  values.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+/// Every setting of a scene, in `show` order.
+/// Groups other than image, render and output are kernel parameters, applied immediately
+pub const SETTINGS: &[Setting] = &[
+  setting("width", "image", Kind::Int, Some((1.0, 32768.0)), "px", "width of `new` images",
+    |x| x.image.width.to_string(), |x, v| parse(v).map(|v| x.image.width = v)),
+  setting("height", "image", Kind::Int, Some((1.0, 32768.0)), "px", "height of `new` images",
+    |x| x.image.height.to_string(), |x, v| parse(v).map(|v| x.image.height = v)),
+  setting("seed", "image", Kind::Seed, None, "", "seed of `new` images",
+    |x| x.image.seed.map_or("random".into(), |x| x.to_string()),
+    |x, v| Some(v).filter(|&v| v != "random").map(parse).transpose().map(|v| x.image.seed = v)),
+  setting("iter", "render", Kind::Int, Some((1.0, 4294967295.0)), "launches", "iterations of `render`",
+    |x| x.render.iter.to_string(), |x, v| parse(v).map(|v| x.render.iter = v)),
+  setting("dimensions", "render", Kind::IntList, Some((1.0, 65536.0)), "", "worker dimensions of `render`, 1 to 3 values",
+    |x| join(&x.render.dimensions),
+    |x, v| v.split_whitespace().map(parse).collect::<Result<_, _>>().map(|v| x.render.dimensions = v)),
+  setting("until_noise", "render", Kind::Float, Some((0.0, 1.0)), "", "noise estimate that ends `render`, 0 is off",
+    |x| x.render.until_noise.to_string(), |x, v| parse(v).map(|v| x.render.until_noise = v)),
+  setting("alpha", "output", Kind::Choice(&["opaque", "straight", "premultiplied"]), None, "", "density as alpha channel",
+    |x| x.output.alpha.name().into(), |x, v| v.parse().map(|v| x.output.alpha = v)),
+  setting("color", "output", Kind::Text, None, "rrggbb", "foreground color of transparent images",
+    |x| x.output.color.clone(), |x, v| parse_color(v).map(|_| x.output.color = v.into())),
+  setting("depth", "output", Kind::Choice(&["8", "16"]), None, "bits", "bits per channel",
+    |x| x.output.depth.bits().to_string(), |x, v| v.parse().map(|v| x.output.depth = v)),
+  setting("format", "output", Kind::Choice(&["auto", "png", "jpg", "tiff", "bmp", "webp"]), None, "", "file format, auto follows the extension, webp is not supported",
+    |x| x.output.format.map_or("auto", |x| x.extension()).into(),
+    |x, v| Some(v).filter(|&v| v != "auto").map(str::parse).transpose().map(|v| x.output.format = v)),
+  setting("quality", "output", Kind::Int, Some((1.0, 100.0)), "", "jpg quality",
+    |x| x.output.quality.to_string(), |x, v| parse(v).map(|v| x.output.quality = v)),
+  setting("dir", "output", Kind::Text, None, "", "output directory of `save_image`, empty for the current one",
+    |x| x.output.dir.as_ref().map_or(String::new(), |x| x.display().to_string()),
+    |x, v| parse(v).map(|v: PathBuf| x.output.dir = Some(v).filter(|v| !v.as_os_str().is_empty()))),
+  setting("template", "output", Kind::Text, None, "", "file name of `save_image`, see `help`",
+    |x| x.output.template.clone(), |x, v| parse(v).map(|v| x.output.template = v)),
+  setting("a", "formula", Kind::Float, None, "", "added to the exponent, z^(2 + a)",
+    |x| x.params.formula.a.to_string(), |x, v| parse(v).map(|v| x.params.formula.a = v)),
+  setting("b", "formula", Kind::Float, None, "", "scale of the point, pixel * (1 + b)",
+    |x| x.params.formula.b.to_string(), |x, v| parse(v).map(|v| x.params.formula.b = v)),
+  setting("c", "formula", Kind::Float, None, "", "real offset added every iteration",
+    |x| x.params.formula.c.to_string(), |x, v| parse(v).map(|v| x.params.formula.c = v)),
+  setting("d", "formula", Kind::Float, None, "", "imaginary offset added every iteration",
+    |x| x.params.formula.d.to_string(), |x, v| parse(v).map(|v| x.params.formula.d = v)),
+  setting("exposure", "tone", Kind::Float, Some((1e-6, 1e6)), "", "density multiplier",
+    |x| x.params.tone.exposure.to_string(), |x, v| parse(v).map(|v| x.params.tone.exposure = v)),
+  setting("shift", "tone", Kind::Float, None, "", "added to the intensity",
+    |x| x.params.tone.shift.to_string(), |x, v| parse(v).map(|v| x.params.tone.shift = v)),
+  setting("gamma", "tone", Kind::Float, Some((1e-3, 1e3)), "", "intensity curve",
+    |x| x.params.tone.gamma.to_string(), |x, v| parse(v).map(|v| x.params.tone.gamma = v)),
+  setting("center_x", "view", Kind::Float, None, "", "point drawn in the middle of the image, -0.5 0 frames the projection",
+    |x| x.params.view.center[0].to_string(), |x, v| parse(v).map(|v| x.params.view.center[0] = v)),
+  setting("center_y", "view", Kind::Float, None, "", "point drawn in the middle of the image",
+    |x| x.params.view.center[1].to_string(), |x, v| parse(v).map(|v| x.params.view.center[1] = v)),
+  setting("zoom", "view", Kind::Float, Some((1e-9, 1e9)), "x", "magnification",
+    |x| x.params.view.zoom.to_string(), |x, v| parse(v).map(|v| x.params.view.zoom = v)),
+  setting("de_enabled", "density_estimation", Kind::Bool, None, "", "adaptive blur of low-density regions",
+    |x| if x.params.density_estimation.enabled { "on" } else { "off" }.into(),
+    |x, v| parse_bool(v).map(|v| x.params.density_estimation.enabled = v)),
+  setting("de_min", "density_estimation", Kind::Float, Some((0.0, 32.0)), "px", "minimum blur radius",
+    |x| x.params.density_estimation.radius_min.to_string(), |x, v| parse(v).map(|v| x.params.density_estimation.radius_min = v)),
+  setting("de_max", "density_estimation", Kind::Float, Some((0.0, 32.0)), "px", "maximum blur radius",
+    |x| x.params.density_estimation.radius_max.to_string(), |x, v| parse(v).map(|v| x.params.density_estimation.radius_max = v)),
+  setting("de_curve", "density_estimation", Kind::Float, Some((0.0, 100.0)), "", "radius falloff with sample count",
+    |x| x.params.density_estimation.curve.to_string(), |x, v| parse(v).map(|v| x.params.density_estimation.curve = v)),
+  setting("preview_growth", "preview", Kind::Float, Some((1.0, 100.0)), "x", "preview interval factor after each preview",
+    |x| x.params.preview.growth.to_string(), |x, v| parse(v).map(|v| x.params.preview.growth = v)),
+  setting("preview_max_interval", "preview", Kind::Int, Some((1.0, 65536.0)), "launches", "longest interval between previews",
+    |x| x.params.preview.max_interval.to_string(), |x, v| parse(v).map(|v| x.params.preview.max_interval = v))
+];
+
+pub fn find_setting(name: &str) -> Result<&'static Setting, String> {
+  SETTINGS.iter()
+    .find(|x| x.name == name)
+    .ok_or_else(|| format!("unknown setting \"{}\", see `show`", name))
+}
+
+pub fn parse_bool(value: &str) -> Result<bool, String> {
+  match value {
+    "on" | "true" => Ok(true),
+    "off" | "false" => Ok(false),
+    _ => Err(format!("expected on or off, got \"{}\"", value))
+  }
+}
+
+impl Setting {
+  /// Value in `scene`, as `set` accepts it
+  pub fn get(&self, scene: &Scene) -> String {
+    (self.get)(scene)
+  }
+
+  /// Checks the type and range, not the constraints between values
+  pub fn set(&self, scene: &mut Scene, value: &str) -> Result<(), String> {
+    self.check(value)?;
+    (self.set)(scene, value)
+  }
+
+  /// Kernel parameters live in the opencl thread, the others in the session
+  pub fn is_param(&self) -> bool {
+    !matches!(self.group, "image" | "render" | "output")
+  }
+
+  fn check_range(&self, x: f64) -> Result<(), String> {
+    match self.range {
+      Some((min, max)) if x < min || x > max => Err(format!("{} must be in {}..={}", self.name, min, max)),
+      _ => Ok(())
+    }
+  }
+
+  /// Nearest value within the range
+  pub fn clamp(&self, x: f64) -> f64 {
+    match self.range {
+      Some((min, max)) => x.max(min).min(max),
+      None => x
+    }
+  }
+
+  /// Type and range of a value, as given to `set`
+  pub fn check(&self, value: &str) -> Result<(), String> {
+    let invalid = || format!("invalid value \"{}\" for {}", value, self.name);
+    match self.kind {
+      Kind::Float => {
+        let x: f64 = value.parse().map_err(|_| invalid())?;
+        if !x.is_finite() {
+          return Err(invalid());
+        }
+        self.check_range(x)
+      },
+      Kind::Int => self.check_range(value.parse::<u64>().map_err(|_| invalid())? as f64),
+      Kind::Seed if value == "random" => Ok(()),
+      Kind::Seed => value.parse::<u64>().map(|_| ()).map_err(|_| invalid()),
+      Kind::Bool => parse_bool(value).map(|_| ()),
+      Kind::Choice(values) if values.contains(&value) => Ok(()),
+      Kind::Choice(values) => Err(format!("{} must be one of: {}", self.name, values.join(" "))),
+      Kind::Text => Ok(()),
+      Kind::IntList => {
+        let values = value.split_whitespace()
+          .map(|x| x.parse::<u64>().map_err(|_| invalid()))
+          .collect::<Result<Vec<_>, _>>()?;
+        if values.is_empty() || values.len() > 3 {
+          return Err(format!("{} must have 1 to 3 values", self.name));
+        }
+        values.iter().try_for_each(|&x| self.check_range(x as f64))
+      }
+    }
+  }
+
+  /// Next value up or down from the one in `scene`, for the controls of the ui.
+  /// None for values without an order
+  pub fn step(&self, scene: &Scene, up: bool) -> Option<String> {
+    let value = self.get(scene);
+    match self.kind {
+      Kind::Float => {
+        let x: f64 = value.parse().ok()?;
+        let x = match self.range {
+          Some((min, _)) if min > 0.0 => if up { x * STEP_FACTOR } else { x / STEP_FACTOR },
+          _ => {
+            let step = if self.group == "view" { STEP / scene.params.view.zoom as f64 } else { STEP };
+            if up { x + step } else { x - step }
+          }
+        };
+        Some((self.clamp(x) as f32).to_string())
+      },
+      Kind::Int => {
+        let x: f64 = value.parse().ok()?;
+        Some((self.clamp(if up { x + 1.0 } else { x - 1.0 }) as u64).to_string())
+      },
+      Kind::Bool => Some(if up { "on" } else { "off" }.into()),
+      Kind::Choice(values) => {
+        let index = values.iter().position(|&x| x == value)?;
+        let next = if up { index + 1 } else { index + values.len() - 1 };
+        Some(values[next % values.len()].into())
+      },
+      Kind::Seed | Kind::Text | Kind::IntList => None
+    }
+  }
+
+  /// e.g. "1..=100 px"
+  pub fn describe_range(&self) -> String {
+    let range = match (self.kind, self.range) {
+      (Kind::Choice(values), _) => values.join("|"),
+      (Kind::Bool, _) => "on|off".into(),
+      (Kind::Seed, _) => "u64|random".into(),
+      (_, Some((min, max))) => format!("{}..={}", min, max),
+      _ => String::new()
+    };
+    format!("{} {}", range, self.unit).trim().to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn round_trip() {
+    let scene = Scene::default();
+    for setting in SETTINGS {
+      let value = setting.get(&scene);
+      let mut copy = Scene::default();
+      setting.set(&mut copy, &value).unwrap_or_else(|e| panic!("{}: {}", setting.name, e));
+      assert_eq!(copy, scene, "{}", setting.name);
+    }
+  }
+
+  #[test]
+  fn set() {
+    let mut scene = Scene::default();
+    for (name, value) in &[
+      ("seed", "7"), ("dimensions", "64 32"), ("format", "tiff"), ("dir", "renders"), ("de_enabled", "on"), ("zoom", "2.5")
+    ] {
+      scene.set(name, value).unwrap();
+      assert_eq!(scene.get(name).unwrap(), *value);
+    }
+    scene.set("seed", "random").unwrap();
+    scene.set("format", "auto").unwrap();
+    scene.set("dir", "").unwrap();
+    assert_eq!((scene.image.seed, scene.output.format, &scene.output.dir), (None, None, &None));
+    assert!(scene.set("color", "red").is_err());
+    assert!(scene.set("unknown", "1").is_err());
+  }
+
+  #[test]
+  fn check() {
+    let find = |name| find_setting(name).unwrap();
+    assert!(find("width").check("512").is_ok());
+    assert!(find("width").check("0").is_err());
+    assert!(find("width").check("-1").is_err());
+    assert!(find("exposure").check("inf").is_err());
+    assert!(find("exposure").check("NaN").is_err());
+    assert!(find("exposure").check("0").is_err());
+    assert!(find("shift").check("-3.5").is_ok());
+    assert!(find("seed").check("random").is_ok());
+    assert!(find("seed").check("-1").is_err());
+    assert!(find("de_enabled").check("off").is_ok());
+    assert!(find("de_enabled").check("yes").is_err());
+    assert!(find("depth").check("16").is_ok());
+    assert!(find("depth").check("12").is_err());
+    assert!(find("dimensions").check("8 8 8").is_ok());
+    assert!(find("dimensions").check("8 8 8 8").is_err());
+    assert!(find("dimensions").check("").is_err());
+    assert!(find("dimensions").check("8 0").is_err());
+    assert_eq!(find("de_max").clamp(40.0), 32.0);
+    assert_eq!(find("shift").clamp(-40.0), -40.0);
+  }
+
+  #[test]
+  fn step() {
+    let find = |name| find_setting(name).unwrap();
+    let mut scene = Scene::default();
+    assert_eq!(find("exposure").step(&scene, true).unwrap(), "1.25");
+    assert_eq!(find("exposure").step(&scene, false).unwrap(), "0.8");
+    assert_eq!(find("shift").step(&scene, false).unwrap(), "-0.05");
+    assert_eq!(find("de_min").step(&scene, false).unwrap(), "0");
+    assert_eq!(find("de_enabled").step(&scene, true).unwrap(), "on");
+    assert_eq!(find("depth").step(&scene, false).unwrap(), "16");
+    assert_eq!(find("preview_max_interval").step(&scene, true).unwrap(), "129");
+    scene.set("zoom", "10").unwrap();
+    assert_eq!(find("center_y").step(&scene, true).unwrap(), "0.005");
+    assert_eq!(find("seed").step(&scene, true), None);
+    // every step of a ui control is accepted by `set`
+    for setting in SETTINGS.iter().filter(|x| x.is_param()) {
+      for &up in &[true, false] {
+        if let Some(value) = setting.step(&scene, up) {
+          setting.set(&mut scene.clone(), &value).unwrap_or_else(|e| panic!("{}: {}", setting.name, e));
+        }
+      }
+    }
+  }
+}
\ No newline at end of file
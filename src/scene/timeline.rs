@@ -0,0 +1,141 @@
+use serde::{Serialize, Deserialize};
+use crate::opencl::RenderParams;
+use super::find_setting;
+
+/// Easing of the segment that starts at a keyframe
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+  Linear,
+  #[default]
+  Smoothstep,
+  /// Catmull-Rom through the neighbouring keyframes
+  Spline
+}
+
+impl std::str::FromStr for Interpolation {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "linear" => Ok(Interpolation::Linear),
+      "smoothstep" => Ok(Interpolation::Smoothstep),
+      "spline" => Ok(Interpolation::Spline),
+      _ => Err(format!("unknown interpolation \"{}\"", s))
+    }
+  }
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keyframe {
+  /// any unit, the animation spans the first to the last keyframe
+  pub time: f32,
+  pub interpolation: Interpolation,
+  pub params: RenderParams
+}
+
+/// Parameters sampled at `time`, keyframes must be sorted by time.
+/// Scalars listed in `RenderParams::NAMES` are interpolated, zoom in log space
+/// so that zooms progress at a constant rate; the rest is taken from the segment start.
+/// Splines may overshoot, values are clamped to the ranges of the settings registry
+pub fn sample_keyframes(keyframes: &[Keyframe], time: f32) -> Option<RenderParams> {
+  let last = keyframes.len().checked_sub(1)?;
+  let segment = keyframes.iter().rposition(|k| k.time <= time).unwrap_or(0).min(last.saturating_sub(1));
+  let k1 = &keyframes[segment];
+  let k2 = &keyframes[(segment + 1).min(last)];
+  let k0 = &keyframes[segment.saturating_sub(1)];
+  let k3 = &keyframes[(segment + 2).min(last)];
+
+  let u = if k2.time > k1.time {
+    ((time - k1.time) / (k2.time - k1.time)).clamp(0.0, 1.0)
+  } else {
+    0.0
+  };
+
+  let mut params = k1.params.clone();
+  for &name in RenderParams::NAMES {
+    let value = |k: &Keyframe| {
+      let x = k.params.get(name).unwrap();
+      if name == "zoom" { x.ln() } else { x }
+    };
+    let (p0, p1, p2, p3) = (value(k0), value(k1), value(k2), value(k3));
+    let x = match k1.interpolation {
+      Interpolation::Linear => p1 + (p2 - p1) * u,
+      Interpolation::Smoothstep => p1 + (p2 - p1) * u * u * (3.0 - 2.0 * u),
+      Interpolation::Spline => 0.5 * (
+        2.0 * p1 +
+        (p2 - p0) * u +
+        (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * u * u +
+        (3.0 * p1 - p0 - 3.0 * p2 + p3) * u * u * u
+      )
+    };
+    let x = if name == "zoom" { x.exp() } else { x };
+    let x = find_setting(name).map_or(x, |setting| setting.clamp(x as f64) as f32);
+    params.set(name, x).unwrap();
+  }
+  let de = &mut params.density_estimation;
+  de.radius_min = de.radius_min.min(de.radius_max);
+  Some(params)
+}
+
+pub fn validate_keyframes(keyframes: &[Keyframe]) -> Result<(), String> {
+  for (i, keyframe) in keyframes.iter().enumerate() {
+    keyframe.params.validate().map_err(|e| format!("keyframe {}: {}", keyframe.time, e))?;
+    if i > 0 && keyframes[i - 1].time >= keyframe.time {
+      return Err("keyframe times must be strictly increasing".into());
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn keyframe(time: f32, interpolation: Interpolation, exposure: f32, zoom: f32) -> Keyframe {
+    let mut params = RenderParams::default();
+    params.tone.exposure = exposure;
+    params.view.zoom = zoom;
+    Keyframe { time, interpolation, params }
+  }
+
+  #[test]
+  fn linear() {
+    let keyframes = [keyframe(0.0, Interpolation::Linear, 1.0, 1.0), keyframe(2.0, Interpolation::Linear, 3.0, 100.0)];
+    let params = sample_keyframes(&keyframes, 1.0).unwrap();
+    assert!((params.tone.exposure - 2.0).abs() < 1e-5);
+    // log space, halfway is the geometric mean
+    assert!((params.view.zoom - 10.0).abs() < 1e-3);
+    assert_eq!(sample_keyframes(&keyframes, -1.0).unwrap().tone.exposure, 1.0);
+    assert_eq!(sample_keyframes(&keyframes, 5.0).unwrap().tone.exposure, 3.0);
+    assert!(sample_keyframes(&[], 0.0).is_none());
+  }
+
+  #[test]
+  fn smoothstep() {
+    let keyframes = [keyframe(0.0, Interpolation::Smoothstep, 1.0, 1.0), keyframe(1.0, Interpolation::Smoothstep, 2.0, 1.0)];
+    let quarter = sample_keyframes(&keyframes, 0.25).unwrap().tone.exposure;
+    assert!(quarter > 1.0 && quarter < 1.25);
+    assert!((sample_keyframes(&keyframes, 0.5).unwrap().tone.exposure - 1.5).abs() < 1e-5);
+  }
+
+  #[test]
+  fn spline_overshoot_is_clamped() {
+    // a steep drop next to a flat segment overshoots below zero
+    let mut keyframes = vec![
+      keyframe(0.0, Interpolation::Spline, 1e-6, 1.0),
+      keyframe(1.0, Interpolation::Spline, 1e-6, 1.0),
+      keyframe(2.0, Interpolation::Spline, 1000.0, 1.0)
+    ];
+    for (i, k) in keyframes.iter_mut().enumerate() {
+      k.params.density_estimation.radius_max = [0.0, 0.0, 32.0][i];
+    }
+    for step in 0..=20 {
+      let params = sample_keyframes(&keyframes, step as f32 / 10.0).unwrap();
+      assert!(params.tone.exposure >= 1e-6);
+      assert!(params.density_estimation.radius_max >= 0.0);
+      params.validate().unwrap();
+    }
+  }
+}
\ No newline at end of file
@@ -4,7 +4,8 @@ use std::{
 };
 use orbtk::{prelude::*, render::platform::RenderContext2D, utils};
 use term_painter::{ToStyle, Color as TColor};
-use crate::opencl;
+use crate::{batch, opencl, repl};
+use crate::scene::{self, Scene, Setting, SETTINGS};
 use crate::lib::debug;
 
 #[derive(Default, AsAny)]
@@ -54,6 +55,43 @@ impl render::RenderPipeline for Graphic2DPipeline {
   }
 }
 
+/// width of the settings panel
+const CONTROLS_WIDTH: f64 = 240.0;
+
+/// `-`, name and `+` of each kernel parameter in the settings registry
+fn controls(ctx: &mut BuildContext) -> Entity {
+  let mut controls = Stack::create().orientation("vertical").margin((8.0, 4.0, 8.0, 0.0));
+  for setting in SETTINGS.iter().filter(|x| x.is_param()) {
+    let step_button = move |text: &str, up: bool, ctx: &mut BuildContext| Button::create()
+      .text(text)
+      .size(24.0, 22.0)
+      .on_click(move |_states, _| {
+        step_setting(setting, up);
+        true
+      })
+      .build(ctx);
+    let row = Stack::create()
+      .orientation("horizontal")
+      .margin((0.0, 2.0, 0.0, 0.0))
+      .child(step_button("-", false, ctx))
+      .child(
+        TextBlock::create()
+          .text(setting.name)
+          .width(CONTROLS_WIDTH - 72.0)
+          .margin((8.0, 0.0, 8.0, 0.0))
+          .vertical_alignment("center")
+          .build(ctx)
+      )
+      .child(step_button("+", true, ctx))
+      .build(ctx);
+    controls = controls.child(row);
+  }
+  controls
+    .attach(Grid::row(1))
+    .attach(Grid::column(1))
+    .build(ctx)
+}
+
 impl Template for MainView {
   fn template(self, id: Entity, ctx: &mut BuildContext) -> Self {
     self.name("MainView")
@@ -66,6 +104,12 @@ impl Template for MainView {
               .row("*")
               .build(),
           )
+          .columns(
+            Columns::create()
+              .column(512.0)
+              .column(CONTROLS_WIDTH)
+              .build(),
+          )
           .child(
             Grid::create()
               .rows(
@@ -93,12 +137,13 @@ impl Template for MainView {
                   .margin((8.0, 8.0, 0.0, 0.0))
                   .size(100.0, 30.0)
                   .on_click(move |_states, _|{
-                    println!("> new --dimensions 512 512");
+                    let image = session().image;
+                    println!("> new --dimensions {} {}", image.width, image.height);
                     unsafe {
                       if let (Some(tx1), Some(rx2)) = (&crate::TX1, &crate::RX2) {
                         let tx1 = tx1.lock().unwrap();
                         let rx2 = rx2.lock().unwrap();
-                        tx1.send(opencl::Action::New(512, 512)).unwrap();
+                        tx1.send(opencl::Action::New(image.width, image.height, image.seed)).unwrap();
                         rx2.recv().unwrap();
                       }
                     }
@@ -114,12 +159,20 @@ impl Template for MainView {
                   .margin((8.0, 8.0, 0.0, 0.0))
                   .size(100.0, 30.0)
                   .on_click(move |_states, _|{
-                    println!("> render -i 64 --dimensions 512 512");
+                    let render = session().render;
+                    let limits = opencl::RenderLimits {
+                      noise: Some(render.until_noise).filter(|&x| x > 0.0),
+                      ..opencl::RenderLimits::default()
+                    };
+                    println!(
+                      "> render -i {} --dimensions {}",
+                      render.iter, render.dimensions.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" ")
+                    );
                     unsafe {
                       if let (Some(tx1), Some(rx2)) = (&crate::TX1, &crate::RX2) {
                         let tx1 = tx1.lock().unwrap();
                         let rx2 = rx2.lock().unwrap();
-                        tx1.send(opencl::Action::Render(64, vec![512, 512], None)).unwrap();
+                        tx1.send(opencl::Action::Render(render.iter, render.dimensions, None, None, None, limits, None)).unwrap();
                         rx2.recv().unwrap();
                       }
                     }
@@ -156,12 +209,23 @@ impl Template for MainView {
                   .margin((8.0, 8.0, 0.0, 0.0))
                   .size(100.0, 30.0)
                   .on_click(move |_states, _|{
-                    println!("> save_image");
+                    // the native dialog confirms overwriting by itself
+                    let path = match tinyfiledialogs::save_file_dialog("Save image", "opencl_attractor.png") {
+                      Some(path) => path,
+                      None => return true
+                    };
+                    println!("> save_image -o \"{}\" --force", path);
+                    let options = session().save_options().unwrap_or_default();
                     unsafe {
                       if let (Some(tx1), Some(rx2)) = (&crate::TX1, &crate::RX2) {
                         let tx1 = tx1.lock().unwrap();
                         let rx2 = rx2.lock().unwrap();
-                        tx1.send(opencl::Action::SaveImage).unwrap();
+                        tx1.send(opencl::Action::SaveImage(opencl::SaveOptions {
+                          output: Some(path.into()),
+                          format: None,
+                          force: true,
+                          ..options
+                        })).unwrap();
                         rx2.recv().unwrap();
                       }
                     }
@@ -174,24 +238,57 @@ impl Template for MainView {
           .child(
             Canvas::create()
               .attach(Grid::column(0))
-              .attach(Grid::column_span(3))
               .attach(Grid::row(1))
               .horizontal_alignment(utils::Alignment::Stretch)
               .render_pipeline(id)
               .build(ctx)
           )
+          .child(controls(ctx))
           .build(ctx)
       )
   }
 }
 
+/// Runs `set <name> <value>` with the next value of a control, as the repl does
+fn step_setting(setting: &Setting, up: bool) {
+  unsafe {
+    if let (Some(tx1), Some(rx2), Some(session)) = (&crate::TX1, &crate::RX2, &crate::SESSION) {
+      // in the order of the repl
+      let tx1 = tx1.lock().unwrap();
+      let rx2 = rx2.lock().unwrap();
+      let mut session = session.lock().expect("mutex is poisoned");
+      let value = batch::current_params(&tx1, &rx2)
+        .map(|params| session.params = params)
+        .ok()
+        .and_then(|_| setting.step(&session, up));
+      if let Some(value) = value {
+        println!("> set {} {}", setting.name, value);
+        match repl::set_settings(&tx1, &rx2, &mut session, &[(setting.name, value.as_str())]) {
+          Ok(()) => println!("{} = {}", setting.name, session.get(setting.name).unwrap_or_default()),
+          Err(e) => println!("{} {}", TColor::BrightRed.paint("ui::err:"), e)
+        }
+      }
+    }
+  }
+}
+
+/// Settings of the repl session, locked before and separately from the channels
+fn session() -> Scene {
+  unsafe {
+    match &crate::SESSION {
+      Some(session) => session.lock().expect("mutex is poisoned").clone(),
+      None => scene::defaults()
+    }
+  }
+}
+
 pub fn init() {
   Application::new()
     .window(move |ctx| {
       Window::create()
         .title("OpenCL Attractor")
         .position((100.0, 100.0))
-        .size(512.0, 512.0 + 46.0)
+        .size(512.0 + CONTROLS_WIDTH, 512.0 + 46.0)
         .child(MainView::create().build(ctx))
         .build(ctx)
     })